@@ -1,37 +1,31 @@
 #![recursion_limit = "512"]
 
 use anyhow::Result;
-use prost::Message;
-use tokio::{
-    io::{AsyncReadExt},
-    net::TcpListener,
-};
+use tokio::net::{TcpListener, TcpStream};
 
-use haveno::generated::io_haveno_protobuffer::NetworkEnvelope;
+use haveno::utils::network::codec::{CodecConfig, EnvelopeStream, Framing};
 
-/// Read and decode a length-prefixed NetworkEnvelope
-async fn recv_envelope(mut socket: tokio::net::TcpStream) -> Result<()> {
-    loop {
-        let mut len_buf = [0u8; 4];
-        if let Err(e) = socket.read_exact(&mut len_buf).await {
-            eprintln!("[✘] Failed to read length prefix: {}", e);
-            break;
-        }
-
-        let len = u32::from_be_bytes(len_buf) as usize;
-        let mut body = vec![0u8; len];
-        if let Err(e) = socket.read_exact(&mut body).await {
-            eprintln!("[✘] Failed to read envelope body: {}", e);
-            break;
-        }
+/// Read and print every envelope a peer sends, whichever length prefix it uses
+async fn recv_envelopes(socket: TcpStream) -> Result<()> {
+    let config = CodecConfig {
+        framing: Framing::Auto,
+        read_timeout: None,
+        ..CodecConfig::default()
+    };
+    let mut stream = EnvelopeStream::with_config(socket, config);
 
-        match NetworkEnvelope::decode(&*body) {
-            Ok(envelope) => {
-                println!("[📦] Received envelope: {:#?}", envelope);
+    loop {
+        match stream.read_envelope().await {
+            Ok(Some(envelope)) => {
+                println!("[📦] Received envelope ({:?} framing): {:#?}", stream.framing(), envelope);
+            }
+            Ok(None) => {
+                println!("[-] Peer closed the connection");
+                break;
             }
             Err(e) => {
-                eprintln!("[!] Failed to decode envelope: {}", e);
-                println!("Raw: {:?}", body);
+                eprintln!("[✘] Failed to read envelope: {}", e);
+                break;
             }
         }
     }
@@ -48,9 +42,9 @@ async fn main() -> Result<()> {
         let (socket, addr) = listener.accept().await?;
         println!("[+] Accepted connection from: {}", addr);
         tokio::spawn(async move {
-            if let Err(e) = recv_envelope(socket).await {
+            if let Err(e) = recv_envelopes(socket).await {
                 eprintln!("[!] Error handling connection from {}: {}", addr, e);
             }
         });
     }
-}
\ No newline at end of file
+}
@@ -6,7 +6,11 @@ struct FilterSetApp;
 #[async_trait::async_trait]
 impl ApplicationEventHandler for FilterSetApp {
     async fn on_application_start(&self) -> Result<()> {
-        bootstrap::run_seed_bootstrap().await
+        // Catch up from the configured seed first, without it we serve what we had
+        if let Err(e) = bootstrap::run_seed_bootstrap().await {
+            eprintln!("⚠️ Bootstrap from seed node failed: {e}");
+        }
+        bootstrap::run_seed_node().await
     }
 
     async fn on_data_update(&self) -> Result<()> {
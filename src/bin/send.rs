@@ -1,36 +1,41 @@
 use haveno::generated::io_haveno_protobuffer::StoragePayload;
 use haveno::builders::{add_data, filter};
 use haveno::utils::network::envelope::build_envelope;
+use haveno::utils::network::updated_data;
 use anyhow::Result;
-use haveno::utils::network::envelope::{send_envelope, recv_envelope, EnvMsg};
-use tokio_socks::tcp::Socks5Stream;
+use haveno::utils::network::codec::EnvelopeStream;
+use haveno::utils::network::envelope::EnvMsg;
+use haveno::p2p::address::NodeAddress;
+use haveno::p2p::transport::configured_transport;
+use haveno::utils::signing::load_key_ring;
 use haveno::generated::io_haveno_protobuffer::{
-    NetworkEnvelope, PreliminaryGetDataRequest, storage_payload::Message as PayloadMessage,
+    NetworkEnvelope, storage_payload::Message as PayloadMessage,
 };
 
 /// this should be trigger as part of an event onApplicationStart() but as part of a routine on a thread for other stuff and runs as daemon
 #[tokio::main]
 async fn main() -> Result<()> {
-    let onion_addr = "cylylutdwlnc4ml3isimvlmfotsgizmpyl2lz65hpvretxfbpwktt2qd.onion:80";
-    let mut stream = Socks5Stream::connect("127.0.0.1:9050", onion_addr)
-        .await?.into_inner();
+    // Usage: send [host:port]
+    let onion_addr = std::env::args().nth(1)
+        .unwrap_or_else(|| "cylylutdwlnc4ml3isimvlmfotsgizmpyl2lz65hpvretxfbpwktt2qd.onion:80".into());
+    let address: NodeAddress = onion_addr.parse()?;
+    let mut stream = EnvelopeStream::new(configured_transport()?.connect(&address.host_name(), address.port).await?);
     println!("🧅 Connected to {onion_addr}");
 
+    // No local stores here, so ask for everything
+    let request = updated_data::build_preliminary_get_data(vec![], "1.1.2");
+    let nonce = request.nonce;
     let request_env = NetworkEnvelope {
         message_version: "0X".into(),
-        message: Some(EnvMsg::PreliminaryGetDataRequest(PreliminaryGetDataRequest {
-            nonce: 4232,
-            excluded_keys: vec![],
-            version: "1.1.2".to_string(),
-            supported_capabilities: [11,14,16].to_vec(),
-        })),
+        message: Some(EnvMsg::PreliminaryGetDataRequest(request)),
     };
 
-    send_envelope(&mut stream, &request_env).await?;
+    stream.write_envelope(&request_env).await?;
     println!("📤 Sent PreliminaryGetDataRequest");
 
-    match recv_envelope(&mut stream).await? {
+    match stream.read_envelope().await? {
         Some(NetworkEnvelope { message: Some(EnvMsg::GetDataResponse(resp)), .. }) => {
+            updated_data::check_get_data_response(&resp, nonce, false)?;
             println!("📥 Received GetDataResponse:");
             println!("  Request nonce: {}", resp.request_nonce);
             println!("  Supported capabilities: {:?}", resp.supported_capabilities);
@@ -45,7 +50,8 @@ async fn main() -> Result<()> {
     }
 
     // Now build and send filter object
-    let filter = filter::build_signed_filter().await?;
+    let key_ring = load_key_ring()?;
+    let filter = filter::build_signed_filter(&key_ring)?;
 
     // Wrap it in the PayloadMessage enum
     let payload = PayloadMessage::Filter(filter);
@@ -53,13 +59,13 @@ async fn main() -> Result<()> {
     // Build AddDataMessage from it
     let signed_add_data_message = add_data::build_signed_add_data_message(StoragePayload {
         message: Some(payload),
-    }).await?;
+    }, &key_ring)?;
 
     // Wrap it in a NetworkEnvelope
     let network_envelope = build_envelope(EnvMsg::AddDataMessage(signed_add_data_message));
 
     // Send
-    send_envelope(&mut stream, &network_envelope).await?;
+    stream.write_envelope(&network_envelope).await?;
     println!("📤 Sent AddDataMessage with Filter");
 
     Ok(())
@@ -1,14 +1,8 @@
-use openssl::bn::BigNum;
-use openssl::dsa::Dsa;
-use openssl::pkey::PKey;
-use openssl::sha::sha256;
-use openssl::sign::Signer;
 use prost::Message;
 use anyhow::{Result};
 use base64::{engine::general_purpose, Engine as _};
-use anyhow::{anyhow};
 
-use crate::utils::signing::{load_private_key};
+use crate::crypto::key_ring::KeyRing;
 use crate::generated::io_haveno_protobuffer::{Filter};
 fn build_filter() -> Filter {
     Filter {
@@ -42,60 +36,22 @@ fn build_filter() -> Filter {
 }
 
 
-/// Generate a base64 signature over the SHA-256 of the serialized Filter
-/// Load the private key from config and sign the filter
-pub async fn sign_filter(filter: &Filter) -> Result<(String, Vec<u8>)> {
-    // Serialize Filter to bytes
-    let mut buf = Vec::new();
-    filter.encode(&mut buf); // Assume success
-
-    // SHA-256 digest
-    let digest = sha256(&buf);
-
-    // Load raw 32-byte private key (hex format from config)
-    let hex_key = load_private_key().await?;
-    let priv_bytes: [u8; 32] = hex::decode(hex_key.trim())
-        .map_err(|e| anyhow!("Invalid hex key: {}", e))?
-        .try_into()
-        .map_err(|_| anyhow!("Expected 32-byte key"))?;
-
-    // Generate DSA domain params (must match Java side exactly in production)
-    let dsa = Dsa::generate(2048)?; // (p, q, g)
-
-    // Inject raw private key into DSA structure
-    let priv_bn = BigNum::from_slice(&priv_bytes)?;
-    let dsa = Dsa::from_private_components(
-        dsa.p().to_owned()?,
-        dsa.q().to_owned()?,
-        dsa.g().to_owned()?,
-        priv_bn,
-        dsa.pub_key().to_owned()?,
-    )?;
-
-    // Create PKey from DSA
-    let pkey = PKey::from_dsa(dsa)?;
-
-    // Sign the digest manually using SHA256withDSA
-    let mut signer = Signer::new_without_digest(&pkey)?;
-    signer.update(&digest)?;
-    let signature = signer.sign_to_vec()?;
-
-    // Export public key as DER (X.509 format for Java)
-    let pubkey_der = pkey.public_key_to_der()?;
-
-    Ok((general_purpose::STANDARD.encode(signature), pubkey_der))
+/// Sign the serialized Filter with our signature key, returning the base64 signature and the
+/// X.509 DER public key it verifies under
+pub fn sign_filter(filter: &Filter, key_ring: &KeyRing) -> Result<(String, Vec<u8>)> {
+    let signature = key_ring.signature().sign(&filter.encode_to_vec())?;
+    Ok((general_purpose::STANDARD.encode(signature), key_ring.signature().public_key_der()?))
 }
 
-pub async fn build_signed_filter() -> Result<Filter> {
+pub fn build_signed_filter(key_ring: &KeyRing) -> Result<Filter> {
     let mut filter = build_filter();
     
     // Sign the filter and get the signature and public key
-    let (signature_base64, pubkey) = sign_filter(&filter).await?;
+    let (signature_base64, pubkey) = sign_filter(&filter, key_ring)?;
     
     // Set the signature and public key in the filter
     filter.signature_as_base64 = signature_base64;
-    //filter.owner_pub_key_bytes = hex::decode(pubkey_hex).expect("Invalid hex public key");
-    filter.owner_pub_key_bytes = pubkey.clone();
+    filter.owner_pub_key_bytes = pubkey;
 
     Ok(filter)
-}
\ No newline at end of file
+}
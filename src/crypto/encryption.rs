@@ -0,0 +1,139 @@
+use anyhow::{anyhow, bail, Context, Result};
+use openssl::encrypt::{Decrypter, Encrypter};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::Signer;
+use openssl::symm::{self, Cipher};
+use std::path::Path;
+use crate::crypto::key_storage::{load_key, save_key};
+
+/// Haveno's `Encryption.generateKeyPair` uses 2048-bit RSA
+const KEY_BITS: u32 = 2048;
+
+/// `Encryption.generateSecretKey(256)`
+pub const SECRET_KEY_LEN: usize = 32;
+
+/// Length of the `HmacSHA256` Haveno appends to payloads before encrypting them
+const HMAC_LEN: usize = 32;
+
+/// Java's `KeyStorage` name for the encryption key, `KeyEntry.MSG_ENCRYPTION`
+const KEY_FILE: &str = "enc.key";
+
+/// Our RSA key for sealed direct messages, the `KeyPair` Haveno calls `encryptionKeyPair`.
+/// Peers encrypt the AES key of a `SealedAndSigned` to its public half.
+pub struct EncryptionKeyPair {
+    key: PKey<Private>,
+}
+
+impl EncryptionKeyPair {
+    pub fn generate() -> Result<Self> {
+        let rsa = Rsa::generate(KEY_BITS)?;
+        Ok(Self { key: PKey::from_rsa(rsa)? })
+    }
+
+    /// From a PKCS#8 DER private key, what Java's `PrivateKey.getEncoded` returns
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        Self::from_key(PKey::private_key_from_pkcs8(der).context("Invalid PKCS#8 private key")?)
+    }
+
+    fn from_key(key: PKey<Private>) -> Result<Self> {
+        if key.id() != Id::RSA {
+            bail!("Encryption key must be RSA, got {:?}", key.id());
+        }
+        Ok(Self { key })
+    }
+
+    pub fn private_key_der(&self) -> Result<Vec<u8>> {
+        Ok(self.key.private_key_to_pkcs8()?)
+    }
+
+    /// X.509 `SubjectPublicKeyInfo`, as in `PubKeyRing.encryption_pub_key_bytes`
+    pub fn public_key_der(&self) -> Result<Vec<u8>> {
+        Ok(self.key.public_key_to_der()?)
+    }
+
+    /// Recover a secret key a peer wrapped for us with `encrypt_secret_key`
+    pub fn decrypt_secret_key(&self, encrypted: &[u8]) -> Result<Vec<u8>> {
+        let mut decrypter = Decrypter::new(&self.key)?;
+        set_oaep_padding_decrypt(&mut decrypter)?;
+        let mut secret_key = vec![0; decrypter.decrypt_len(encrypted)?];
+        let len = decrypter.decrypt(encrypted, &mut secret_key).context("Failed to decrypt secret key")?;
+        secret_key.truncate(len);
+        if secret_key.len() != SECRET_KEY_LEN {
+            bail!("Decrypted secret key has {} bytes, expected {SECRET_KEY_LEN}", secret_key.len());
+        }
+        Ok(secret_key)
+    }
+
+    pub fn load(dir: &Path, password: Option<&str>) -> Result<Self> {
+        Self::from_key(load_key(dir, KEY_FILE, password)?)
+            .with_context(|| format!("{} is not an RSA encryption key", dir.join(KEY_FILE).display()))
+    }
+
+    pub fn save(&self, dir: &Path, password: Option<&str>) -> Result<()> {
+        save_key(dir, KEY_FILE, &self.key, password)
+    }
+}
+
+/// A fresh AES-256 key, one per sealed message
+pub fn generate_secret_key() -> Result<[u8; SECRET_KEY_LEN]> {
+    let mut secret_key = [0; SECRET_KEY_LEN];
+    openssl::rand::rand_bytes(&mut secret_key)?;
+    Ok(secret_key)
+}
+
+fn set_oaep_padding(encrypter: &mut Encrypter) -> Result<()> {
+    encrypter.set_rsa_padding(Padding::PKCS1_OAEP)?;
+    encrypter.set_rsa_oaep_md(MessageDigest::sha256())?;
+    encrypter.set_rsa_mgf1_md(MessageDigest::sha256())?;
+    Ok(())
+}
+
+fn set_oaep_padding_decrypt(decrypter: &mut Decrypter) -> Result<()> {
+    decrypter.set_rsa_padding(Padding::PKCS1_OAEP)?;
+    decrypter.set_rsa_oaep_md(MessageDigest::sha256())?;
+    decrypter.set_rsa_mgf1_md(MessageDigest::sha256())?;
+    Ok(())
+}
+
+/// Wrap `secret_key` for the holder of the X.509 DER RSA `public_key_der`, as Java's
+/// `RSA/ECB/OAEPWithSHA-256AndMGF1Padding` with SHA-256 MGF1 does
+pub fn encrypt_secret_key(public_key_der: &[u8], secret_key: &[u8]) -> Result<Vec<u8>> {
+    let key = PKey::public_key_from_der(public_key_der).context("Invalid X.509 encryption key")?;
+    let mut encrypter = Encrypter::new(&key)?;
+    set_oaep_padding(&mut encrypter)?;
+    let mut encrypted = vec![0; encrypter.encrypt_len(secret_key)?];
+    let len = encrypter.encrypt(secret_key, &mut encrypted)?;
+    encrypted.truncate(len);
+    Ok(encrypted)
+}
+
+fn hmac(payload: &[u8], secret_key: &[u8]) -> Result<Vec<u8>> {
+    let key = PKey::hmac(secret_key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(payload)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// `payload || HmacSHA256(payload)`, encrypted with Java's default `AES` cipher, which is
+/// AES/ECB/PKCS5Padding
+pub fn encrypt_payload_with_hmac(payload: &[u8], secret_key: &[u8]) -> Result<Vec<u8>> {
+    let mut payload_with_hmac = payload.to_vec();
+    payload_with_hmac.extend_from_slice(&hmac(payload, secret_key)?);
+    Ok(symm::encrypt(Cipher::aes_256_ecb(), secret_key, None, &payload_with_hmac)?)
+}
+
+/// Reverse `encrypt_payload_with_hmac`, failing if the HMAC doesn't match
+pub fn decrypt_payload_with_hmac(encrypted: &[u8], secret_key: &[u8]) -> Result<Vec<u8>> {
+    let payload_with_hmac = symm::decrypt(Cipher::aes_256_ecb(), secret_key, None, encrypted)
+        .map_err(|_| anyhow!("Failed to decrypt payload"))?;
+    if payload_with_hmac.len() < HMAC_LEN {
+        bail!("Decrypted payload is too short to hold an HMAC");
+    }
+    let (payload, expected) = payload_with_hmac.split_at(payload_with_hmac.len() - HMAC_LEN);
+    if !openssl::memcmp::eq(&hmac(payload, secret_key)?, expected) {
+        bail!("Payload HMAC does not match");
+    }
+    Ok(payload.to_vec())
+}
@@ -0,0 +1,103 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+use crate::crypto::encryption::EncryptionKeyPair;
+use crate::crypto::sig::SignatureKeyPair;
+use crate::generated::io_haveno_protobuffer::PubKeyRing;
+
+/// Our signature and encryption keys, which together identify us to Haveno peers.
+///
+/// Kept in a key directory as `sig.key` and `enc.key`, like Java's `KeyStorage`. With a
+/// password both files are encrypted, which Haveno can't read, so only set one for keys
+/// that stay with the Rust node.
+pub struct KeyRing {
+    signature: SignatureKeyPair,
+    encryption: EncryptionKeyPair,
+}
+
+impl KeyRing {
+    pub fn new(signature: SignatureKeyPair, encryption: EncryptionKeyPair) -> Self {
+        Self { signature, encryption }
+    }
+
+    pub fn generate() -> Result<Self> {
+        Ok(Self::new(SignatureKeyPair::generate()?, EncryptionKeyPair::generate()?))
+    }
+
+    pub fn signature(&self) -> &SignatureKeyPair {
+        &self.signature
+    }
+
+    pub fn encryption(&self) -> &EncryptionKeyPair {
+        &self.encryption
+    }
+
+    /// The public halves, as we advertise them in offers, trades and sealed messages
+    pub fn pub_key_ring(&self) -> Result<PubKeyRing> {
+        Ok(PubKeyRing {
+            signature_pub_key_bytes: self.signature.public_key_der()?,
+            encryption_pub_key_bytes: self.encryption.public_key_der()?,
+        })
+    }
+
+    pub fn load(dir: &Path, password: Option<&str>) -> Result<Self> {
+        Ok(Self::new(SignatureKeyPair::load(dir, password)?, EncryptionKeyPair::load(dir, password)?))
+    }
+
+    pub fn save(&self, dir: &Path, password: Option<&str>) -> Result<()> {
+        self.signature.save(dir, password)?;
+        self.encryption.save(dir, password)
+    }
+
+    /// Load the key ring from `dir`, or generate and save one there on first run. A directory
+    /// holding only one of the keys is an error rather than something we quietly complete.
+    pub fn load_or_generate(dir: &Path, password: Option<&str>) -> Result<Self> {
+        match (dir.join("sig.key").exists(), dir.join("enc.key").exists()) {
+            (true, true) => Self::load(dir, password),
+            (false, false) => {
+                let key_ring = Self::generate()?;
+                key_ring.save(dir, password)?;
+                println!("🔑 Generated key ring in {}", dir.display());
+                Ok(key_ring)
+            }
+            _ => bail!("{} holds only one of sig.key and enc.key", dir.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("key-ring-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn reloads_the_same_keys() {
+        let dir = temp_dir();
+        let key_ring = KeyRing::load_or_generate(&dir, None).unwrap();
+        let loaded = KeyRing::load_or_generate(&dir, None).unwrap();
+        assert_eq!(loaded.pub_key_ring().unwrap(), key_ring.pub_key_ring().unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn encrypted_keys_need_the_password() {
+        let dir = temp_dir();
+        let key_ring = KeyRing::load_or_generate(&dir, Some("hunter2")).unwrap();
+
+        assert!(KeyRing::load(&dir, None).is_err());
+        assert!(KeyRing::load(&dir, Some("wrong")).is_err());
+        let loaded = KeyRing::load(&dir, Some("hunter2")).unwrap();
+        assert_eq!(loaded.pub_key_ring().unwrap(), key_ring.pub_key_ring().unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_half_written_key_dir() {
+        let dir = temp_dir();
+        SignatureKeyPair::generate().unwrap().save(&dir, None).unwrap();
+        assert!(KeyRing::load_or_generate(&dir, None).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use openssl::pkey::{PKey, Private};
+use openssl::symm::Cipher;
+use std::fs;
+use std::path::Path;
+
+/// Read a PKCS#8 DER private key, decrypting it with `password` if one is set.
+///
+/// Unencrypted files are what Java's `KeyStorage` writes; encrypted ones are standard
+/// PBES2 PKCS#8, which Haveno can't read.
+pub(crate) fn load_key(dir: &Path, file: &str, password: Option<&str>) -> Result<PKey<Private>> {
+    let path = dir.join(file);
+    let der = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let key = match password {
+        Some(password) => PKey::private_key_from_pkcs8_passphrase(&der, password.as_bytes()),
+        None => PKey::private_key_from_pkcs8(&der),
+    };
+    key.with_context(|| format!("Failed to decode {}, wrong or missing key password?", path.display()))
+}
+
+/// Write `key` as PKCS#8 DER, encrypted with AES-256 under `password` if one is set, and
+/// readable by us only
+pub(crate) fn save_key(dir: &Path, file: &str, key: &PKey<Private>, password: Option<&str>) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let der = match password {
+        Some(password) => key.private_key_to_pkcs8_passphrase(Cipher::aes_256_cbc(), password.as_bytes())?,
+        None => key.private_key_to_pkcs8()?,
+    };
+
+    let path = dir.join(file);
+    fs::write(&path, der).with_context(|| format!("Failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
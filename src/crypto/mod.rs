@@ -0,0 +1,5 @@
+pub mod sig;
+pub mod encryption;
+pub mod key_ring;
+pub mod sealed;
+mod key_storage;
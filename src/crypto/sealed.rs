@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use openssl::sha::sha256;
+use prost::Message;
+use crate::crypto::encryption::{decrypt_payload_with_hmac, encrypt_payload_with_hmac, encrypt_secret_key, generate_secret_key};
+use crate::crypto::key_ring::KeyRing;
+use crate::crypto::sig;
+use crate::generated::io_haveno_protobuffer::{NetworkEnvelope, PubKeyRing, SealedAndSigned};
+
+/// A message opened with `unseal`, Haveno's `DecryptedDataTuple`
+#[derive(Debug, Clone)]
+pub struct Unsealed {
+    pub envelope: NetworkEnvelope,
+    /// X.509 DER signature key of the sender, which signed the sealed message
+    pub sender_sig_pub_key: Vec<u8>,
+}
+
+/// Encrypt `envelope` for `receiver` and sign it with our key, as Haveno's
+/// `EncryptionService.encryptHybridWithSignature` does.
+///
+/// The envelope is encrypted with a fresh AES key, which is itself encrypted to the receiver's
+/// RSA key. The signature covers the SHA-256 of the encrypted payload.
+pub fn seal(envelope: &NetworkEnvelope, receiver: &PubKeyRing, key_ring: &KeyRing) -> Result<SealedAndSigned> {
+    let secret_key = generate_secret_key()?;
+    let encrypted_secret_key = encrypt_secret_key(&receiver.encryption_pub_key_bytes, &secret_key)?;
+    let encrypted_payload_with_hmac = encrypt_payload_with_hmac(&envelope.encode_to_vec(), &secret_key)?;
+    let signature = key_ring.signature().sign(&sha256(&encrypted_payload_with_hmac))?;
+
+    Ok(SealedAndSigned {
+        encrypted_secret_key,
+        encrypted_payload_with_hmac,
+        signature,
+        sig_public_key_bytes: key_ring.signature().public_key_der()?,
+    })
+}
+
+/// Decrypt a message sealed for us and check its signature, Haveno's
+/// `EncryptionService.decryptHybridWithSignature`
+pub fn unseal(sealed: &SealedAndSigned, key_ring: &KeyRing) -> Result<Unsealed> {
+    let secret_key = key_ring.encryption().decrypt_secret_key(&sealed.encrypted_secret_key)?;
+    sig::verify(
+        &sealed.sig_public_key_bytes,
+        &sha256(&sealed.encrypted_payload_with_hmac),
+        &sealed.signature,
+    )
+    .context("Sealed message signature verification failed")?;
+
+    let payload = decrypt_payload_with_hmac(&sealed.encrypted_payload_with_hmac, &secret_key)?;
+    let envelope = NetworkEnvelope::decode(payload.as_slice()).context("Sealed payload is not a NetworkEnvelope")?;
+    Ok(Unsealed {
+        envelope,
+        sender_sig_pub_key: sealed.sig_public_key_bytes.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::encryption::EncryptionKeyPair;
+    use crate::crypto::sig::SignatureKeyPair;
+    use crate::generated::io_haveno_protobuffer::Ping;
+    use crate::utils::network::envelope::EnvMsg;
+    use hex_literal::hex;
+
+    // Vectors made with Python's `cryptography` following Haveno's `Encryption` and
+    // `EncryptionService`: RSA/OAEP-SHA256 key wrap, AES/ECB/PKCS5 over payload || HmacSHA256,
+    // and SHA256withDSA over the SHA-256 of the encrypted payload.
+
+    /// `message_version: "0X"`, `Ping { nonce: 42 }`
+    const ENVELOPE: [u8; 8] = hex!("0a0230583a02082a");
+    const PAYLOAD_KEY: [u8; 32] = hex!("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+    const ENCRYPTED_PAYLOAD_WITH_HMAC: [u8; 48] = hex!(
+        "d8a1f00726cee701b5b9f35729de5ba1aba95f9066e2e53d846b62453f5dfcdc0b6738ecf4975b443628b50a56b5502e"
+    );
+
+    /// A 1024-bit RSA receiver key, PKCS#8 DER
+    const RECEIVER_KEY: [u8; 635] = hex!(
+        "30820277020100300d06092a864886f70d0101010500048202613082025d02010002818100b5ddbe235877524acb9fa1bfeb4aba42"
+        "24c55220715be980bd5a38b2102af756a99d04c4e5b8a11d476bbb9ad7ab5f7ce446a84c3d46e24e2a748cedc7512387f0f7d96462"
+        "5fba4bedb2b83f6e67ded2ceb370a6c4fbaf379745e78475b075a206422e6b46d33ce18887064ffb28dcac10a24a5a4c138e5556d2"
+        "066b237dfe65020301000102818042e00641f30ef578c185b2b1e2f0b8ab07e07341b0cd8e80a3a06086c1c13af068307007833db0"
+        "2917113b8c45d023adc637613d9faec81adc2bbd82d62f34f3f2504c0c020a97926894c3ea1227b6b4e3b6cd46574f38f887c2ccbf"
+        "866d98f71c08d8736ad58ced0003cf6c9eb6207a6da1b70eace1d6b3c9095405a3631365024100e0019b56b2d92d0148a45a0f5614"
+        "ca2ade2b1ac8f3cea146d40d0703e80fd714d6aaa1edb9e1cbd458b65ff2e2a620a2af774690bc134b31cd5d34f0d51f6b7b024100"
+        "cfd75ba2857c0f87164df0be0ed9eaff5703b2db7c07237709c9474e7f1acc955ad94466a7c16b135bf9ea3a7c2d4afe36ba6d3197"
+        "ba9f8fc3e9a3194887a79f024006dc25bcaef775a85f682115ed25efd7063cbdb23fbd29353913390ec6918d9da6c14cfa31c21772"
+        "e6509779a1b542b498b90761315149f1397fcc3a3fdc9977024100a62c3b398f9c07162c81184ffcb3c2ad7036a6d3d1302beccac8"
+        "40d8c3cafb731675cbb87fbba70e8d651d1eb195ccf5bf700249ba541daae62a709a9060d565024100c2841e5e64ab35b13027238e"
+        "c33f5ab991f27eaabfdb600febb4b9b10094d742088e49aa8bc620839aa4879d927d56118720c3358050c3f377a0777a07af90d4"
+    );
+
+    fn python_sealed() -> SealedAndSigned {
+        SealedAndSigned {
+            encrypted_secret_key: hex!(
+                "3faf9c32b6cf888c9edd6ebf48e555f418998b5da754ceeca47e2ac572ff4efbc6c252c847864b2f89eaa74b25f4b31c"
+                "c78c03c6063a58ea49bae2cb3585c55dca0f6dfcf79da1e76261c0eb9da2fb3b6226b15a1e9f28c04a2a1395db3b5e53"
+                "b317d83bced3d5c32fe30995cd8fb6db14dd7f766ef79cc789d326d20b0eb34b"
+            )
+            .to_vec(),
+            encrypted_payload_with_hmac: hex!(
+                "d85553380a938d2a4fbe74077b4db6a96f907c0ac30d053ed8d81ccd94927ab7b0f01a96cd286fb2cdd01bc44193d813"
+            )
+            .to_vec(),
+            signature: hex!("302c0214189c0b4a441161b4e0624c8ab52740cbc346b4090214598be3fa238c53851928cbba99941b773d0f4e10")
+                .to_vec(),
+            sig_public_key_bytes: hex!(
+                "308201b63082012b06072a8648ce3804013082011e028181008202cbc94a76c38554f1d587a65928fb9a1f7546326f8ea80d"
+                "fd5cc9f10454aece1bf10bfa032917a6d450c2c600a290118cff9f6a7add0ae21afb5b2d839db8ed68f9cf23f536425a546c"
+                "3ec3d0715d17759342bfa47a58911613276f4a1c7b6372984e6a06b1129d1458469da7527930d75da1f2c984468cabfabd70"
+                "aaf391021500deaa3a89951badfc5aa73112645583eb3cb5c2d10281803facef39860777387c4e4b4cf66c8f2f3ac406ada6"
+                "80c8fc75f1f8621761e20928d9812a5e2bb955a1ff1aa618e872610493445d5e81d18d176f4a1bf319186fdb8640927d7b3a"
+                "685e4e0a2ec47258276d984f4424c406e7fc4f5535f9e82062ac89ebdeb479c48bfdde3b764770e41d03a51728941b1efac6"
+                "8e578d40c5bf810381840002818044df59b0b1792d73b43d38e758f01796627c8b7f2b2b90016039cf62daa92a707f6f9d0b"
+                "de5745861c26a80055fc2ba3968165930a44eef3007f05bf3b06bf315933d96d06050187ee50e80cd38ed85cc7b3f1011781"
+                "a9c3494cba5714f2ae7f78227eb35255f0c1000145d9908a55776dd3a13df12d3035231329c34092d86f"
+            )
+            .to_vec(),
+        }
+    }
+
+    fn ping_envelope() -> NetworkEnvelope {
+        NetworkEnvelope {
+            message_version: "0X".into(),
+            message: Some(EnvMsg::Ping(Ping {
+                nonce: 42,
+                ..Default::default()
+            })),
+        }
+    }
+
+    #[test]
+    fn payload_encryption_matches_haveno_layout() {
+        assert_eq!(ping_envelope().encode_to_vec(), ENVELOPE);
+        assert_eq!(encrypt_payload_with_hmac(&ENVELOPE, &PAYLOAD_KEY).unwrap(), ENCRYPTED_PAYLOAD_WITH_HMAC);
+        assert_eq!(decrypt_payload_with_hmac(&ENCRYPTED_PAYLOAD_WITH_HMAC, &PAYLOAD_KEY).unwrap(), ENVELOPE);
+
+        let mut tampered = ENCRYPTED_PAYLOAD_WITH_HMAC;
+        tampered[0] ^= 1;
+        assert!(decrypt_payload_with_hmac(&tampered, &PAYLOAD_KEY).is_err());
+    }
+
+    #[test]
+    fn unseals_messages_sealed_by_another_implementation() {
+        let receiver = KeyRing::new(
+            SignatureKeyPair::generate().unwrap(),
+            EncryptionKeyPair::from_pkcs8_der(&RECEIVER_KEY).unwrap(),
+        );
+        let sealed = python_sealed();
+
+        let unsealed = unseal(&sealed, &receiver).unwrap();
+        assert_eq!(unsealed.envelope, ping_envelope());
+        assert_eq!(unsealed.sender_sig_pub_key, sealed.sig_public_key_bytes);
+    }
+
+    #[test]
+    fn round_trips_between_key_rings() {
+        let sender = KeyRing::generate().unwrap();
+        let receiver = KeyRing::generate().unwrap();
+
+        let sealed = seal(&ping_envelope(), &receiver.pub_key_ring().unwrap(), &sender).unwrap();
+        let unsealed = unseal(&sealed, &receiver).unwrap();
+        assert_eq!(unsealed.envelope, ping_envelope());
+        assert_eq!(unsealed.sender_sig_pub_key, sender.signature().public_key_der().unwrap());
+
+        // Only the receiver can open it
+        assert!(unseal(&sealed, &sender).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_or_resigned_messages() {
+        let sender = KeyRing::generate().unwrap();
+        let receiver = KeyRing::generate().unwrap();
+        let sealed = seal(&ping_envelope(), &receiver.pub_key_ring().unwrap(), &sender).unwrap();
+
+        let mut tampered = sealed.clone();
+        tampered.encrypted_payload_with_hmac[0] ^= 1;
+        assert!(unseal(&tampered, &receiver).is_err());
+
+        // Claiming another sender key fails the signature check
+        let mut impersonated = sealed;
+        impersonated.sig_public_key_bytes = receiver.signature().public_key_der().unwrap();
+        assert!(unseal(&impersonated, &receiver).is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{anyhow, bail, Context, Result};
+use openssl::dsa::Dsa;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::sign::{Signer, Verifier};
+use std::path::Path;
+use crate::crypto::key_storage::{load_key, save_key};
+
+/// Haveno's `Sig.generateKeyPair` uses 1024-bit DSA
+const KEY_BITS: u32 = 1024;
+
+/// Java's `KeyStorage` name for the signature key, `KeyEntry.MSG_SIGNATURE`
+const KEY_FILE: &str = "sig.key";
+
+/// Our storage signature key, the DSA `KeyPair` Haveno calls `signatureKeyPair`.
+///
+/// Signs as Java's `SHA256withDSA`, so `Sig.verify` on Haveno nodes accepts our signatures.
+/// Saved like `KeyStorage` does, as the PKCS#8 encoded private key in `sig.key`; the public
+/// key is derived from it on load.
+pub struct SignatureKeyPair {
+    key: PKey<Private>,
+}
+
+impl SignatureKeyPair {
+    pub fn generate() -> Result<Self> {
+        let dsa = Dsa::generate(KEY_BITS)?;
+        Ok(Self { key: PKey::from_dsa(dsa)? })
+    }
+
+    /// From a PKCS#8 DER private key, what Java's `PrivateKey.getEncoded` returns
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        Self::from_key(PKey::private_key_from_pkcs8(der).context("Invalid PKCS#8 private key")?)
+    }
+
+    fn from_key(key: PKey<Private>) -> Result<Self> {
+        if key.id() != Id::DSA {
+            bail!("Signature key must be DSA, got {:?}", key.id());
+        }
+        Ok(Self { key })
+    }
+
+    pub fn private_key_der(&self) -> Result<Vec<u8>> {
+        Ok(self.key.private_key_to_pkcs8()?)
+    }
+
+    /// X.509 `SubjectPublicKeyInfo`, what Haveno puts in `owner_pub_key_bytes`
+    pub fn public_key_der(&self) -> Result<Vec<u8>> {
+        Ok(self.key.public_key_to_der()?)
+    }
+
+    /// `SHA256withDSA` over `data`, DER encoded as Java produces it
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+        signer.update(data)?;
+        Ok(signer.sign_to_vec()?)
+    }
+
+    pub fn load(dir: &Path, password: Option<&str>) -> Result<Self> {
+        Self::from_key(load_key(dir, KEY_FILE, password)?)
+            .with_context(|| format!("{} is not a DSA signature key", dir.join(KEY_FILE).display()))
+    }
+
+    pub fn save(&self, dir: &Path, password: Option<&str>) -> Result<()> {
+        save_key(dir, KEY_FILE, &self.key, password)
+    }
+}
+
+/// Check a `SHA256withDSA` signature against an X.509 DER public key, like Haveno's `Sig.verify`
+pub fn verify(public_key_der: &[u8], data: &[u8], signature: &[u8]) -> Result<()> {
+    let key = PKey::public_key_from_der(public_key_der).context("Invalid X.509 public key")?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+    verifier.update(data)?;
+    // Malformed signatures are an openssl error rather than `false`
+    if verifier.verify(signature).unwrap_or(false) {
+        Ok(())
+    } else {
+        Err(anyhow!("Signature does not match the public key"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("sig-key-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn signatures_verify_under_our_public_key_only() {
+        let key = SignatureKeyPair::generate().unwrap();
+        let signature = key.sign(b"payload").unwrap();
+
+        verify(&key.public_key_der().unwrap(), b"payload", &signature).unwrap();
+        assert!(verify(&key.public_key_der().unwrap(), b"other payload", &signature).is_err());
+        assert!(verify(&key.public_key_der().unwrap(), b"payload", b"garbage").is_err());
+
+        let other = SignatureKeyPair::generate().unwrap();
+        assert!(verify(&other.public_key_der().unwrap(), b"payload", &signature).is_err());
+    }
+
+    #[test]
+    fn reloads_the_same_key_from_disk() {
+        let dir = temp_dir();
+        let key = SignatureKeyPair::generate().unwrap();
+        key.save(&dir, None).unwrap();
+        let loaded = SignatureKeyPair::load(&dir, None).unwrap();
+        assert_eq!(loaded.public_key_der().unwrap(), key.public_key_der().unwrap());
+
+        let signature = loaded.sign(b"payload").unwrap();
+        verify(&key.public_key_der().unwrap(), b"payload", &signature).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_dsa() {
+        let rsa = PKey::from_rsa(openssl::rsa::Rsa::generate(2048).unwrap()).unwrap();
+        assert!(SignatureKeyPair::from_pkcs8_der(&rsa.private_key_to_pkcs8().unwrap()).is_err());
+    }
+}
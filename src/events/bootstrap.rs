@@ -1,76 +1,71 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use std::sync::Arc;
+use tokio::io::AsyncRead;
 use crate::{
-    generated::{self, io_haveno_protobuffer::{
+    crypto::key_ring::KeyRing,
+    generated::io_haveno_protobuffer::{
         network_envelope::Message as EnvMsg,
         storage_payload::Message as PayloadMessage,
-        NetworkEnvelope, PreliminaryGetDataRequest, StoragePayload
-    }}, builders::{add_data, filter}, p2p::{handlers::add_data::AddDataMessageHandler, router::P2PMessageRouter}, utils::{
+        GetDataResponse, NetworkEnvelope, StoragePayload
+    }, builders::{add_data, filter}, p2p::{self, address::NodeAddress, append_only_store::AppendOnlyDataStore, data_storage::P2PDataStorage, handlers::{add_data::AddDataMessageHandler, add_persistable_network_payload::AddPersistableNetworkPayloadMessageHandler, refresh_offer::RefreshOfferMessageHandler, remove_data::RemoveDataMessageHandler}, router::P2PMessageRouter}, utils::{
+        config::Config,
         network::{
-            ack, envelope::{build_envelope, recv_envelope, send_envelope}, updated_data
-        },
-        signing
+            codec::EnvelopeStream, envelope::build_envelope, updated_data
+        }
     }
 };
-use tokio_socks::tcp::Socks5Stream;
 
 /// Entry point to run the seed bootstrap procedure
 pub async fn run_seed_bootstrap() -> Result<()> {
 
+    let json = std::fs::read_to_string("config.json")?;
+    let config: Config = serde_json::from_str(&json)?;
+
     // Start the network listener
     let router = P2PMessageRouter::new();
-    router.register("AddDataMessage", *Box::new(AddDataMessageHandler)).await;
-
-
-    let onion_addr = "5i6blbmuflq4s4im6zby26a7g22oef6kyp7vbwyru6oq5e36akzo3ayd.onion:2001";
-    let mut stream = Socks5Stream::connect("127.0.0.1:9050", onion_addr)
-        .await?
-        .into_inner();
-
-    println!("\n🧅 Connected to {onion_addr}");
-
-    // Build and send the PreliminaryGetDataRequest
+    let storage = Arc::new(P2PDataStorage::open(&config.db_dir, chrono::Utc::now().timestamp_millis())?);
+    router.register(AddDataMessageHandler::new(storage.clone())).await;
+    RemoveDataMessageHandler::new(storage.clone()).register(&router).await;
+    router.register(RefreshOfferMessageHandler::new(storage.clone())).await;
+    let store = Arc::new(AppendOnlyDataStore::open(&config.db_dir)?);
+    router.register(AddPersistableNetworkPayloadMessageHandler::new(store.clone())).await;
+
+    let seed_node: NodeAddress = config.seed_node.parse()
+        .context("seed_node must be host:port")?;
+    let transport = config.transport.build();
+    let mut stream = EnvelopeStream::new(transport.connect(&seed_node.host_name(), seed_node.port).await?);
+
+    println!("\n🧅 Connected to {seed_node}");
+
+    // Build and send the PreliminaryGetDataRequest, leaving out what we already have
+    let request = updated_data::build_preliminary_get_data(updated_data::excluded_keys(&storage, &store).await, "1.1.2");
+    let nonce = request.nonce;
     let request_env = NetworkEnvelope {
         message_version: "0X".into(),
-        message: Some(EnvMsg::PreliminaryGetDataRequest(PreliminaryGetDataRequest {
-            nonce: 4232,
-            excluded_keys: vec![],
-            version: "1.1.2".to_string(),
-            supported_capabilities: vec![11, 14, 16],
-        })),
+        message: Some(EnvMsg::PreliminaryGetDataRequest(request)),
     };
 
-    send_envelope(&mut stream, &request_env).await?;
+    stream.write_envelope(&request_env).await?;
     println!("📤 Sent PreliminaryGetDataRequest");
 
     // Wait for and process GetDataResponse
-    if let Some(envelope) = recv_envelope(&mut stream).await? {
-        match &envelope.message {
-            Some(EnvMsg::GetDataResponse(resp)) => {
-                println!("📥 Received GetDataResponse:");
-                println!("  Request nonce: {}", resp.request_nonce);
-                println!("  Supported capabilities: {:?}", resp.supported_capabilities);
-                println!("  Persistable network payloads: {:?}", resp.persistable_network_payload_items);
-            }
-            Some(other) => println!("⚠️ Unexpected envelope message: {:?}", other),
-            None => println!("⚠️ Envelope has no message field."),
-        }
-    }
+    let response = read_get_data_response(&mut stream, nonce, false).await?;
+    apply_get_data_response(response, &storage, &store).await;
 
     // Must now GetDataUpdateRequest
-    let get_updated_data = updated_data::build_get_updated_data().await?;
+    let get_updated_data = updated_data::build_get_updated_data(updated_data::excluded_keys(&storage, &store).await).await?;
+    let nonce = get_updated_data.nonce;
     let update_data_network_envelope = build_envelope(EnvMsg::GetUpdatedDataRequest(get_updated_data));
-    send_envelope(&mut stream, &update_data_network_envelope).await?;
+    stream.write_envelope(&update_data_network_envelope).await?;
     println!("📤 Sent GetUpdatedDataRequest");
 
-    // Must now response with AckMessage before sending anything else
-    let ack_envelope = ack::build_ack().await?;
-    let ack_network_envelope = build_envelope(EnvMsg::AckMessage(ack_envelope));
-    send_envelope(&mut stream, &ack_network_envelope).await?;
-    println!("📤 Sent AckMessage");
+    let response = read_get_data_response(&mut stream, nonce, true).await?;
+    apply_get_data_response(response, &storage, &store).await;
 
 
     // Now build and send filter object
-    let filter = filter::build_signed_filter().await?;
+    let key_ring = KeyRing::load_or_generate(&config.key_dir, config.key_password.as_deref())?;
+    let filter = filter::build_signed_filter(&key_ring)?;
 
     // Wrap it in the PayloadMessage enum
     let payload = PayloadMessage::Filter(filter);
@@ -78,14 +73,88 @@ pub async fn run_seed_bootstrap() -> Result<()> {
     // Build AddDataMessage from it
     let signed_add_data_message = add_data::build_signed_add_data_message(StoragePayload {
         message: Some(payload),
-    }).await?;
+    }, &key_ring)?;
 
     // Wrap it in a NetworkEnvelope
     let network_envelope = build_envelope(EnvMsg::AddDataMessage(signed_add_data_message));
 
     // Send
-    send_envelope(&mut stream, &network_envelope).await?;
+    stream.write_envelope(&network_envelope).await?;
     println!("📤 Sent AddDataMessage with Filter");
 
+    storage.persist().await?;
+    store.persist().await?;
+    Ok(())
+}
+
+/// Read envelopes until the `GetDataResponse` to our request with `nonce`
+async fn read_get_data_response<S: AsyncRead + Unpin>(
+    stream: &mut EnvelopeStream<S>,
+    nonce: i32,
+    is_get_updated_data_response: bool,
+) -> Result<GetDataResponse> {
+    loop {
+        let Some(envelope) = stream.read_envelope().await? else {
+            bail!("Seed node closed the connection before its GetDataResponse");
+        };
+        match envelope.message {
+            Some(EnvMsg::GetDataResponse(resp)) => {
+                updated_data::check_get_data_response(&resp, nonce, is_get_updated_data_response)?;
+                return Ok(resp);
+            }
+            Some(other) => println!("⚠️ Unexpected envelope message: {:?}", other),
+            None => println!("⚠️ Envelope has no message field."),
+        }
+    }
+}
+
+/// Take in the entries and payloads of a `GetDataResponse`
+async fn apply_get_data_response(resp: GetDataResponse, storage: &P2PDataStorage, store: &AppendOnlyDataStore) {
+    println!("📥 Received GetDataResponse:");
+    println!("  Request nonce: {}", resp.request_nonce);
+    println!("  Supported capabilities: {:?}", resp.supported_capabilities);
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let received = resp.data_set.len();
+    let mut added = 0;
+    for entry in resp.data_set.into_iter().filter_map(|wrapper| wrapper.message) {
+        match storage.add(entry, now).await {
+            Ok(true) => added += 1,
+            Ok(false) => {}
+            Err(e) => println!("⚠️ Skipped storage entry: {e}"),
+        }
+    }
+    println!("  Protected storage entries: {received}, {added} new");
+
+    let received = resp.persistable_network_payload_items.len();
+    let mut added = 0;
+    for payload in resp.persistable_network_payload_items {
+        match store.add(payload).await {
+            Ok(true) => added += 1,
+            Ok(false) => {}
+            Err(e) => println!("⚠️ Skipped persistable network payload: {e}"),
+        }
+    }
+    println!("  Persistable network payloads: {received}, {added} new");
+    if resp.was_truncated {
+        println!("  Response was truncated, the rest follows on the next request");
+    }
+}
+
+/// Serve the network data we have to peers on `node_port`, as a seed node does, until Ctrl-C
+pub async fn run_seed_node() -> Result<()> {
+    let json = std::fs::read_to_string("config.json")?;
+    let config: Config = serde_json::from_str(&json)?;
+    let port = u16::try_from(config.node_port).context("node_port must be a valid port")?;
+
+    let storage = Arc::new(P2PDataStorage::open(&config.db_dir, chrono::Utc::now().timestamp_millis())?);
+    let store = Arc::new(AppendOnlyDataStore::open(&config.db_dir)?);
+    let listener = p2p::setup(port, storage.clone(), store.clone()).await?;
+    println!("🌱 Serving as a seed node on port {port}");
+
+    tokio::signal::ctrl_c().await?;
+    listener.shutdown();
+    storage.persist().await?;
+    store.persist().await?;
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,314 @@
+use data_encoding::BASE32_NOPAD;
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use sha3::{Digest, Sha3_256};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use crate::generated::io_haveno_protobuffer as protobuf;
+
+/// The only onion service version Haveno uses
+const ONION_VERSION: u8 = 0x03;
+
+/// Why an address or a signature over it was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    /// Not `<56 base32 characters>.onion`
+    NotOnion(String),
+    /// Decodes fine but isn't a v3 address
+    UnsupportedVersion(u8),
+    /// The checksum doesn't match the key, usually a typo
+    BadChecksum,
+    InvalidPublicKey,
+    InvalidSignature,
+    /// The signature is well formed but wasn't made by the address's key
+    SignatureMismatch,
+    MissingPort(String),
+    InvalidPort(String),
+    InvalidHost(String),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotOnion(address) => write!(f, "{address:?} is not a v3 onion address"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported onion address version {version}"),
+            Self::BadChecksum => write!(f, "onion address checksum does not match"),
+            Self::InvalidPublicKey => write!(f, "onion address does not hold a valid ed25519 key"),
+            Self::InvalidSignature => write!(f, "malformed ed25519 signature"),
+            Self::SignatureMismatch => write!(f, "signature was not made by the onion address's key"),
+            Self::MissingPort(address) => write!(f, "{address:?} has no port"),
+            Self::InvalidPort(port) => write!(f, "invalid port {port:?}"),
+            Self::InvalidHost(host) => write!(f, "invalid host {host:?}"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// A v3 onion address, checked for version and checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OnionAddress {
+    public_key: PublicKey,
+}
+
+impl OnionAddress {
+    pub fn from_public_key(public_key: PublicKey) -> Self {
+        Self { public_key }
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// The address without `.onion`, which is what Tor's control port calls the service id
+    pub fn service_id(&self) -> String {
+        let mut bytes = self.public_key.to_bytes().to_vec();
+        bytes.extend_from_slice(&checksum(self.public_key.as_bytes()));
+        bytes.push(ONION_VERSION);
+        BASE32_NOPAD.encode(&bytes).to_lowercase()
+    }
+
+    /// Check that `signature` over `message` was made by this address's key
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), AddressError> {
+        let signature = Signature::from_bytes(signature).map_err(|_| AddressError::InvalidSignature)?;
+        self.public_key
+            .verify(message, &signature)
+            .map_err(|_| AddressError::SignatureMismatch)
+    }
+}
+
+/// H(".onion checksum" || pubkey || version)[:2]
+fn checksum(public_key: &[u8; 32]) -> [u8; 2] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(b".onion checksum");
+    hasher.update(public_key);
+    hasher.update([ONION_VERSION]);
+    let hash = hasher.finalize();
+    [hash[0], hash[1]]
+}
+
+impl FromStr for OnionAddress {
+    type Err = AddressError;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let not_onion = || AddressError::NotOnion(address.to_string());
+        let service_id = address.strip_suffix(".onion").ok_or_else(not_onion)?;
+        if service_id.len() != 56 {
+            return Err(not_onion());
+        }
+
+        // pubkey (32) || checksum (2) || version (1)
+        let bytes = BASE32_NOPAD
+            .decode(service_id.to_uppercase().as_bytes())
+            .map_err(|_| not_onion())?;
+        if bytes[34] != ONION_VERSION {
+            return Err(AddressError::UnsupportedVersion(bytes[34]));
+        }
+        let public_key: [u8; 32] = bytes[..32].try_into().map_err(|_| not_onion())?;
+        if bytes[32..34] != checksum(&public_key) {
+            return Err(AddressError::BadChecksum);
+        }
+
+        let public_key = PublicKey::from_bytes(&public_key).map_err(|_| AddressError::InvalidPublicKey)?;
+        Ok(Self { public_key })
+    }
+}
+
+impl fmt::Display for OnionAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.onion", self.service_id())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Onion(OnionAddress),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// A DNS name, for clearnet test networks
+    Hostname(String),
+}
+
+impl FromStr for Host {
+    type Err = AddressError;
+
+    fn from_str(host: &str) -> Result<Self, Self::Err> {
+        if host.ends_with(".onion") {
+            return host.parse().map(Self::Onion);
+        }
+        if let Ok(ip) = host.parse() {
+            return Ok(Self::Ipv4(ip));
+        }
+        if let Some(ip) = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host).parse().ok() {
+            return Ok(Self::Ipv6(ip));
+        }
+
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        };
+        if host.len() <= 253 && host.split('.').all(valid_label) {
+            Ok(Self::Hostname(host.to_ascii_lowercase()))
+        } else {
+            Err(AddressError::InvalidHost(host.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Onion(onion) => write!(f, "{onion}"),
+            Self::Ipv4(ip) => write!(f, "{ip}"),
+            Self::Ipv6(ip) => write!(f, "{ip}"),
+            Self::Hostname(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A validated peer address, converting to and from the protobuf `NodeAddress`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeAddress {
+    pub host: Host,
+    pub port: u16,
+}
+
+impl NodeAddress {
+    pub fn new(host: Host, port: u16) -> Self {
+        Self { host, port }
+    }
+
+    /// The host as transports expect it, without IPv6 brackets
+    pub fn host_name(&self) -> String {
+        self.host.to_string()
+    }
+
+    pub fn onion(&self) -> Option<&OnionAddress> {
+        match &self.host {
+            Host::Onion(onion) => Some(onion),
+            _ => None,
+        }
+    }
+}
+
+fn parse_port(port: &str) -> Result<u16, AddressError> {
+    match port.parse() {
+        Ok(0) | Err(_) => Err(AddressError::InvalidPort(port.to_string())),
+        Ok(port) => Ok(port),
+    }
+}
+
+impl FromStr for NodeAddress {
+    type Err = AddressError;
+
+    /// `host:port`, with IPv6 hosts in brackets
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| AddressError::MissingPort(address.to_string()))?;
+        if host.contains(':') && !host.starts_with('[') {
+            // A bare IPv6 address, whose last group isn't a port
+            return Err(AddressError::MissingPort(address.to_string()));
+        }
+        Ok(Self::new(host.parse()?, parse_port(port)?))
+    }
+}
+
+impl fmt::Display for NodeAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.host {
+            Host::Ipv6(ip) => write!(f, "[{ip}]:{}", self.port),
+            host => write!(f, "{host}:{}", self.port),
+        }
+    }
+}
+
+impl TryFrom<&protobuf::NodeAddress> for NodeAddress {
+    type Error = AddressError;
+
+    fn try_from(address: &protobuf::NodeAddress) -> Result<Self, Self::Error> {
+        let port = u16::try_from(address.port)
+            .ok()
+            .filter(|port| *port != 0)
+            .ok_or_else(|| AddressError::InvalidPort(address.port.to_string()))?;
+        Ok(Self::new(address.host_name.parse()?, port))
+    }
+}
+
+impl From<&NodeAddress> for protobuf::NodeAddress {
+    fn from(address: &NodeAddress) -> Self {
+        Self {
+            host_name: address.host_name(),
+            port: address.port.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: &str = "5i6blbmuflq4s4im6zby26a7g22oef6kyp7vbwyru6oq5e36akzo3ayd.onion";
+
+    #[test]
+    fn parses_and_displays_onion_addresses() {
+        let onion: OnionAddress = SEED.parse().unwrap();
+        assert_eq!(onion.to_string(), SEED);
+        assert_eq!(SEED.to_uppercase().replace(".ONION", ".onion").parse(), Ok(onion));
+    }
+
+    #[test]
+    fn rejects_malformed_onion_addresses() {
+        let mut typo = SEED.to_string();
+        typo.replace_range(0..1, "6");
+        assert_eq!(typo.parse::<OnionAddress>(), Err(AddressError::BadChecksum));
+
+        // Re-encode the same key with another version byte
+        let mut bytes = BASE32_NOPAD.decode(SEED[..56].to_uppercase().as_bytes()).unwrap();
+        bytes[34] = 0x02;
+        let v2 = BASE32_NOPAD.encode(&bytes).to_lowercase() + ".onion";
+        assert_eq!(v2.parse::<OnionAddress>(), Err(AddressError::UnsupportedVersion(2)));
+
+        assert!(matches!("abc.onion".parse::<OnionAddress>(), Err(AddressError::NotOnion(_))));
+        assert!(matches!(SEED.replace('a', "1").parse::<OnionAddress>(), Err(AddressError::NotOnion(_))));
+    }
+
+    #[test]
+    fn parses_each_node_address_form() {
+        let onion: NodeAddress = format!("{SEED}:2001").parse().unwrap();
+        assert!(onion.onion().is_some());
+        assert_eq!(onion.port, 2001);
+
+        let v4: NodeAddress = "127.0.0.1:9999".parse().unwrap();
+        assert_eq!(v4.host, Host::Ipv4(Ipv4Addr::LOCALHOST));
+
+        let v6: NodeAddress = "[::1]:9999".parse().unwrap();
+        assert_eq!(v6.host, Host::Ipv6(Ipv6Addr::LOCALHOST));
+        assert_eq!(v6.to_string(), "[::1]:9999");
+        assert_eq!(v6.host_name(), "::1");
+
+        let name: NodeAddress = "Seed.Example.com:80".parse().unwrap();
+        assert_eq!(name.to_string(), "seed.example.com:80");
+
+        assert!(matches!("::1".parse::<NodeAddress>(), Err(AddressError::MissingPort(_))));
+        assert!(matches!("localhost:0".parse::<NodeAddress>(), Err(AddressError::InvalidPort(_))));
+        assert!(matches!("bad_host:80".parse::<NodeAddress>(), Err(AddressError::InvalidHost(_))));
+    }
+
+    #[test]
+    fn converts_to_and_from_protobuf() {
+        let address: NodeAddress = format!("{SEED}:2001").parse().unwrap();
+        let protobuf = protobuf::NodeAddress::from(&address);
+        assert_eq!(protobuf.host_name, SEED);
+        assert_eq!(NodeAddress::try_from(&protobuf), Ok(address));
+
+        let negative_port = protobuf::NodeAddress {
+            host_name: SEED.into(),
+            port: -1,
+        };
+        assert!(NodeAddress::try_from(&negative_port).is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::{bail, Result};
+use openssl::hash::{self, MessageDigest};
+use openssl::sha::sha256;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::{Path, PathBuf};
+use tokio::sync::RwLock;
+use crate::generated::io_haveno_protobuffer::{
+    persistable_envelope::Message as PersistableMessage, persistable_network_payload::Message as NetworkPayloadMessage,
+    AccountAgeWitness, AccountAgeWitnessStore, PersistableEnvelope, PersistableNetworkPayload, SignedWitness,
+    SignedWitnessStore, TradeStatistics3, TradeStatistics3Store,
+};
+use crate::utils::storage::persistence_manager::{read_persistable_envelope, write_persistable_envelope};
+
+/// Persistable network payloads are keyed by a RIPEMD-160 of a SHA-256, Haveno's `getSha256Ripemd160hash`
+pub const NETWORK_PAYLOAD_HASH_SIZE: usize = 20;
+
+const ACCOUNT_AGE_WITNESS_FILE: &str = "AccountAgeWitnessStore";
+const SIGNED_WITNESS_FILE: &str = "SignedWitnessStore";
+const TRADE_STATISTICS_FILE: &str = "TradeStatistics3Store";
+
+/// The key Haveno stores a persistable network payload under, `getHash`.
+///
+/// Account age witnesses and trade statistics carry theirs; a signed witness's is derived from
+/// the witness hash, signature, signer key and date.
+pub fn network_payload_hash(payload: &NetworkPayloadMessage) -> Result<Vec<u8>> {
+    match payload {
+        NetworkPayloadMessage::AccountAgeWitness(witness) => Ok(witness.hash.clone()),
+        NetworkPayloadMessage::TradeStatistics3(statistics) => Ok(statistics.hash.clone()),
+        NetworkPayloadMessage::SignedWitness(witness) => {
+            let data = [
+                witness.account_age_witness_hash.as_slice(),
+                witness.signature.as_slice(),
+                witness.signer_pub_key.as_slice(),
+                &witness.date.to_be_bytes()[..],
+            ]
+            .concat();
+            Ok(hash::hash(MessageDigest::ripemd160(), &sha256(&data))?.to_vec())
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    account_age_witnesses: HashMap<Vec<u8>, AccountAgeWitness>,
+    signed_witnesses: HashMap<Vec<u8>, SignedWitness>,
+    trade_statistics: HashMap<Vec<u8>, TradeStatistics3>,
+    /// Whether anything was added since the stores were last persisted
+    dirty: bool,
+}
+
+impl Inner {
+    fn insert(&mut self, hash: Vec<u8>, payload: NetworkPayloadMessage) -> bool {
+        let added = match payload {
+            NetworkPayloadMessage::AccountAgeWitness(witness) => insert_new(&mut self.account_age_witnesses, hash, witness),
+            NetworkPayloadMessage::SignedWitness(witness) => insert_new(&mut self.signed_witnesses, hash, witness),
+            NetworkPayloadMessage::TradeStatistics3(statistics) => insert_new(&mut self.trade_statistics, hash, statistics),
+        };
+        self.dirty |= added;
+        added
+    }
+}
+
+fn insert_new<T>(items: &mut HashMap<Vec<u8>, T>, hash: Vec<u8>, item: T) -> bool {
+    match items.entry(hash) {
+        Entry::Vacant(vacant) => {
+            vacant.insert(item);
+            true
+        }
+        Entry::Occupied(_) => false,
+    }
+}
+
+/// Account age witnesses, signed witnesses and trade statistics, Haveno's
+/// `AppendOnlyDataStoreService`. Payloads are only ever added, each once by its hash.
+pub struct AppendOnlyDataStore {
+    inner: RwLock<Inner>,
+    /// Where `persist` writes the stores, if anywhere
+    db_dir: Option<PathBuf>,
+}
+
+impl Default for AppendOnlyDataStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppendOnlyDataStore {
+    pub fn new() -> Self {
+        Self {
+            inner: RwLock::new(Inner::default()),
+            db_dir: None,
+        }
+    }
+
+    /// Stores kept in `db_dir` as `AccountAgeWitnessStore`, `SignedWitnessStore` and
+    /// `TradeStatistics3Store`, loading what is persisted there
+    pub fn open(db_dir: &Path) -> Result<Self> {
+        let mut inner = Inner::default();
+        let mut payloads = Vec::new();
+
+        let path = db_dir.join(ACCOUNT_AGE_WITNESS_FILE);
+        if path.exists() {
+            let Some(PersistableMessage::AccountAgeWitnessStore(store)) = read_persistable_envelope(&path)?.message else {
+                bail!("{} doesn't hold an AccountAgeWitnessStore", path.display());
+            };
+            payloads.extend(store.items.into_iter().map(NetworkPayloadMessage::AccountAgeWitness));
+        }
+        let path = db_dir.join(SIGNED_WITNESS_FILE);
+        if path.exists() {
+            let Some(PersistableMessage::SignedWitnessStore(store)) = read_persistable_envelope(&path)?.message else {
+                bail!("{} doesn't hold a SignedWitnessStore", path.display());
+            };
+            payloads.extend(store.items.into_iter().map(NetworkPayloadMessage::SignedWitness));
+        }
+        let path = db_dir.join(TRADE_STATISTICS_FILE);
+        if path.exists() {
+            let Some(PersistableMessage::TradeStatistics3Store(store)) = read_persistable_envelope(&path)?.message else {
+                bail!("{} doesn't hold a TradeStatistics3Store", path.display());
+            };
+            payloads.extend(store.items.into_iter().map(NetworkPayloadMessage::TradeStatistics3));
+        }
+
+        for payload in payloads {
+            let hash = network_payload_hash(&payload)?;
+            if hash.len() == NETWORK_PAYLOAD_HASH_SIZE {
+                inner.insert(hash, payload);
+            }
+        }
+        inner.dirty = false;
+        println!(
+            "💾 Loaded {} account age witnesses, {} signed witnesses and {} trade statistics from {}",
+            inner.account_age_witnesses.len(),
+            inner.signed_witnesses.len(),
+            inner.trade_statistics.len(),
+            db_dir.display()
+        );
+
+        Ok(Self {
+            inner: RwLock::new(inner),
+            db_dir: Some(db_dir.to_path_buf()),
+        })
+    }
+
+    /// Write the stores if anything was added since the last write.
+    /// A no-op for a store made with `new`.
+    pub async fn persist(&self) -> Result<()> {
+        let Some(db_dir) = &self.db_dir else {
+            return Ok(());
+        };
+        let mut inner = self.inner.write().await;
+        if !inner.dirty {
+            return Ok(());
+        }
+
+        let stores = [
+            (ACCOUNT_AGE_WITNESS_FILE, PersistableMessage::AccountAgeWitnessStore(AccountAgeWitnessStore {
+                items: inner.account_age_witnesses.values().cloned().collect(),
+            })),
+            (SIGNED_WITNESS_FILE, PersistableMessage::SignedWitnessStore(SignedWitnessStore {
+                items: inner.signed_witnesses.values().cloned().collect(),
+            })),
+            (TRADE_STATISTICS_FILE, PersistableMessage::TradeStatistics3Store(TradeStatistics3Store {
+                items: inner.trade_statistics.values().cloned().collect(),
+            })),
+        ];
+        for (file, store) in stores {
+            write_persistable_envelope(&db_dir.join(file), &PersistableEnvelope { message: Some(store) })?;
+        }
+        inner.dirty = false;
+        Ok(())
+    }
+
+    /// Add `payload` unless we have it already. Returns whether it was new.
+    pub async fn add(&self, payload: PersistableNetworkPayload) -> Result<bool> {
+        let Some(payload) = payload.message else {
+            bail!("PersistableNetworkPayload without a payload");
+        };
+        let hash = network_payload_hash(&payload)?;
+        if hash.len() != NETWORK_PAYLOAD_HASH_SIZE {
+            bail!("Payload hash is {} bytes, expected {NETWORK_PAYLOAD_HASH_SIZE}", hash.len());
+        }
+        Ok(self.inner.write().await.insert(hash, payload))
+    }
+
+    pub async fn contains(&self, hash: &[u8]) -> bool {
+        let inner = self.inner.read().await;
+        inner.account_age_witnesses.contains_key(hash)
+            || inner.signed_witnesses.contains_key(hash)
+            || inner.trade_statistics.contains_key(hash)
+    }
+
+    pub async fn hashes(&self) -> Vec<Vec<u8>> {
+        let inner = self.inner.read().await;
+        inner
+            .account_age_witnesses
+            .keys()
+            .chain(inner.signed_witnesses.keys())
+            .chain(inner.trade_statistics.keys())
+            .cloned()
+            .collect()
+    }
+
+    /// Every payload we have, with its hash
+    pub async fn payloads(&self) -> Vec<(Vec<u8>, PersistableNetworkPayload)> {
+        let inner = self.inner.read().await;
+        let account_age_witnesses = inner
+            .account_age_witnesses
+            .iter()
+            .map(|(hash, witness)| (hash.clone(), NetworkPayloadMessage::AccountAgeWitness(witness.clone())));
+        let signed_witnesses = inner
+            .signed_witnesses
+            .iter()
+            .map(|(hash, witness)| (hash.clone(), NetworkPayloadMessage::SignedWitness(witness.clone())));
+        let trade_statistics = inner
+            .trade_statistics
+            .iter()
+            .map(|(hash, statistics)| (hash.clone(), NetworkPayloadMessage::TradeStatistics3(statistics.clone())));
+
+        account_age_witnesses
+            .chain(signed_witnesses)
+            .chain(trade_statistics)
+            .map(|(hash, message)| (hash, PersistableNetworkPayload { message: Some(message) }))
+            .collect()
+    }
+
+    pub async fn len(&self) -> usize {
+        let inner = self.inner.read().await;
+        inner.account_age_witnesses.len() + inner.signed_witnesses.len() + inner.trade_statistics.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn witness(seed: u8) -> PersistableNetworkPayload {
+        PersistableNetworkPayload {
+            message: Some(NetworkPayloadMessage::AccountAgeWitness(AccountAgeWitness {
+                hash: vec![seed; NETWORK_PAYLOAD_HASH_SIZE],
+                date: 1_700_000_000_000,
+            })),
+        }
+    }
+
+    fn signed_witness() -> PersistableNetworkPayload {
+        PersistableNetworkPayload {
+            message: Some(NetworkPayloadMessage::SignedWitness(SignedWitness {
+                account_age_witness_hash: vec![1; NETWORK_PAYLOAD_HASH_SIZE],
+                signature: vec![2; 46],
+                signer_pub_key: vec![3; 443],
+                date: 1_700_000_000_000,
+                ..Default::default()
+            })),
+        }
+    }
+
+    #[tokio::test]
+    async fn adds_each_payload_once() {
+        let store = AppendOnlyDataStore::new();
+        assert!(store.add(witness(1)).await.unwrap());
+        assert!(!store.add(witness(1)).await.unwrap());
+        assert!(store.add(witness(2)).await.unwrap());
+        assert!(store.add(signed_witness()).await.unwrap());
+        assert!(!store.add(signed_witness()).await.unwrap());
+
+        assert_eq!(store.len().await, 3);
+        assert!(store.contains(&[1; NETWORK_PAYLOAD_HASH_SIZE]).await);
+    }
+
+    #[tokio::test]
+    async fn refuses_payloads_without_a_proper_hash() {
+        let store = AppendOnlyDataStore::new();
+        let mut short = witness(1);
+        if let Some(NetworkPayloadMessage::AccountAgeWitness(witness)) = &mut short.message {
+            witness.hash.truncate(8);
+        }
+        assert!(store.add(short).await.is_err());
+        assert!(store.add(PersistableNetworkPayload { message: None }).await.is_err());
+        assert!(store.is_empty().await);
+    }
+
+    #[tokio::test]
+    async fn reloads_what_it_persisted() {
+        let dir = std::env::temp_dir().join(format!("append-only-store-test-{}", uuid::Uuid::new_v4()));
+        let store = AppendOnlyDataStore::open(&dir).unwrap();
+        store.add(witness(1)).await.unwrap();
+        store.add(signed_witness()).await.unwrap();
+        store.persist().await.unwrap();
+
+        let reopened = AppendOnlyDataStore::open(&dir).unwrap();
+        let mut hashes: Vec<_> = reopened.payloads().await.into_iter().map(|(hash, _)| hash).collect();
+        let mut expected: Vec<_> = store.payloads().await.into_iter().map(|(hash, _)| hash).collect();
+        hashes.sort();
+        expected.sort();
+        assert_eq!(hashes, expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
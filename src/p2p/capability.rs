@@ -0,0 +1,34 @@
+use crate::generated::io_haveno_protobuffer::{
+    persistable_network_payload::Message as NetworkPayloadMessage, storage_payload::Message as PayloadMessage,
+};
+
+// Haveno's `Capability` enum, by ordinal, as peers list them in `supported_capabilities`
+
+pub const SEED_NODE: i32 = 3;
+pub const SIGNED_ACCOUNT_AGE_WITNESS: i32 = 11;
+pub const MEDIATION: i32 = 12;
+pub const REFUND_AGENT: i32 = 13;
+pub const TRADE_STATISTICS_HASH_UPDATE: i32 = 14;
+pub const TRADE_STATISTICS_3: i32 = 16;
+
+/// What we tell peers we support
+pub const SUPPORTED: [i32; 3] = [SIGNED_ACCOUNT_AGE_WITNESS, TRADE_STATISTICS_HASH_UPDATE, TRADE_STATISTICS_3];
+
+/// The capability a peer needs before we send it this kind of storage payload,
+/// Haveno's `CapabilityRequiringPayload`
+pub fn required_for_storage_payload(payload: &PayloadMessage) -> Option<i32> {
+    match payload {
+        PayloadMessage::Mediator(_) => Some(MEDIATION),
+        PayloadMessage::RefundAgent(_) => Some(REFUND_AGENT),
+        _ => None,
+    }
+}
+
+/// The capability a peer needs before we send it this kind of network payload
+pub fn required_for_network_payload(payload: &NetworkPayloadMessage) -> Option<i32> {
+    match payload {
+        NetworkPayloadMessage::AccountAgeWitness(_) => None,
+        NetworkPayloadMessage::SignedWitness(_) => Some(SIGNED_ACCOUNT_AGE_WITNESS),
+        NetworkPayloadMessage::TradeStatistics3(_) => Some(TRADE_STATISTICS_3),
+    }
+}
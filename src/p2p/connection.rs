@@ -0,0 +1,345 @@
+use anyhow::{bail, Result};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, RwLock};
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::generated::io_haveno_protobuffer::{CloseConnectionMessage, NetworkEnvelope, NodeAddress};
+use crate::p2p::address;
+use crate::p2p::router::P2PMessageRouter;
+use crate::utils::network::codec::{CodecConfig, EnvelopeStream};
+use crate::utils::network::envelope::EnvMsg;
+
+/// Envelopes waiting to be written before `send` starts applying backpressure
+const OUTBOUND_QUEUE_SIZE: usize = 64;
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What we know about the peer on the other end of a connection
+#[derive(Debug, Clone)]
+pub struct ConnectionState {
+    /// Socket address of the remote end, `None` for proxied or in-memory streams
+    pub remote_addr: Option<SocketAddr>,
+    /// The node address the peer reported about itself, if it has sent one yet
+    pub peer_node_address: Option<NodeAddress>,
+    pub capabilities: Vec<i32>,
+    /// `message_version` of the last envelope the peer sent, reused for our own control messages
+    pub message_version: String,
+    pub last_activity: Instant,
+    /// Measured by the keep-alive pings, `None` until the first pong
+    pub round_trip_time: Option<Duration>,
+    /// The onion address the peer proved it controls, see `onion_auth`
+    pub authenticated_address: Option<address::NodeAddress>,
+}
+
+impl ConnectionState {
+    fn new(remote_addr: Option<SocketAddr>) -> Self {
+        Self {
+            remote_addr,
+            peer_node_address: None,
+            capabilities: Vec::new(),
+            message_version: String::new(),
+            last_activity: Instant::now(),
+            round_trip_time: None,
+            authenticated_address: None,
+        }
+    }
+
+    /// Pick up the sender address and capabilities from the messages that carry them
+    fn observe(&mut self, envelope: &NetworkEnvelope) {
+        self.last_activity = Instant::now();
+        if self.message_version != envelope.message_version {
+            self.message_version = envelope.message_version.clone();
+        }
+
+        let (address, capabilities) = match &envelope.message {
+            Some(EnvMsg::PreliminaryGetDataRequest(m)) => (None, Some(&m.supported_capabilities)),
+            Some(EnvMsg::GetDataResponse(m)) => (None, Some(&m.supported_capabilities)),
+            Some(EnvMsg::GetUpdatedDataRequest(m)) => (m.sender_node_address.as_ref(), None),
+            Some(EnvMsg::GetPeersRequest(m)) => (m.sender_node_address.as_ref(), Some(&m.supported_capabilities)),
+            Some(EnvMsg::GetPeersResponse(m)) => (None, Some(&m.supported_capabilities)),
+            Some(EnvMsg::AckMessage(m)) => (m.sender_node_address.as_ref(), None),
+            _ => (None, None),
+        };
+
+        if let Some(address) = address {
+            self.peer_node_address = Some(address.clone());
+        }
+        if let Some(capabilities) = capabilities.filter(|c| !c.is_empty()) {
+            self.capabilities = capabilities.clone();
+        }
+    }
+}
+
+struct ConnectionInner {
+    id: u64,
+    outbound: mpsc::Sender<NetworkEnvelope>,
+    state: RwLock<ConnectionState>,
+    closed: CancellationToken,
+    tasks: TaskTracker,
+}
+
+/// A long-lived connection to a peer.
+///
+/// Incoming envelopes are read in a loop and dispatched through the router, outgoing ones go
+/// through a queue drained by a writer task. The handle is cheap to clone, so handlers can keep
+/// it around to reply later.
+#[derive(Clone)]
+pub struct Connection {
+    inner: Arc<ConnectionInner>,
+}
+
+impl Connection {
+    /// Take over `stream` and start its read and write tasks
+    pub fn spawn<S>(
+        stream: S,
+        remote_addr: Option<SocketAddr>,
+        config: CodecConfig,
+        router: Arc<P2PMessageRouter>,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (outbound, outbound_rx) = mpsc::channel(OUTBOUND_QUEUE_SIZE);
+        let connection = Self {
+            inner: Arc::new(ConnectionInner {
+                id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+                outbound,
+                state: RwLock::new(ConnectionState::new(remote_addr)),
+                closed: CancellationToken::new(),
+                tasks: TaskTracker::new(),
+            }),
+        };
+
+        let (reader, writer) = tokio::io::split(stream);
+        let tasks = &connection.inner.tasks;
+        tasks.spawn(connection.clone().write_loop(EnvelopeStream::with_config(writer, config.clone()), outbound_rx));
+        tasks.spawn(connection.clone().read_loop(EnvelopeStream::with_config(reader, config), router));
+        tasks.close();
+
+        connection
+    }
+
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    pub async fn state(&self) -> ConnectionState {
+        self.inner.state.read().await.clone()
+    }
+
+    pub(crate) async fn update_state<R>(&self, update: impl FnOnce(&mut ConnectionState) -> R) -> R {
+        update(&mut *self.inner.state.write().await)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.is_cancelled()
+    }
+
+    /// Resolves once the connection has been closed by either side
+    pub async fn closed(&self) {
+        self.inner.closed.cancelled().await
+    }
+
+    /// Resolves once the connection is closed and its read and write tasks, including any
+    /// handler still running, have finished
+    pub async fn finished(&self) {
+        self.inner.tasks.wait().await
+    }
+
+    /// Queue an envelope for the peer. Waits if the queue is full.
+    pub async fn send(&self, envelope: NetworkEnvelope) -> Result<()> {
+        if self.is_closed() {
+            bail!("connection {} is closed", self.inner.id);
+        }
+        if self.inner.outbound.send(envelope).await.is_err() {
+            bail!("connection {} is closed", self.inner.id);
+        }
+        Ok(())
+    }
+
+    /// Stop reading, flush whatever is already queued and shut the stream down
+    pub fn close(&self) {
+        self.inner.closed.cancel();
+    }
+
+    /// Tell the peer why we are hanging up with a `CloseConnectionMessage`, then close.
+    /// `reason` is one of Haveno's `CloseConnectionReason` names, e.g. `APP_SHUT_DOWN`.
+    pub async fn close_with_reason(&self, reason: &str) {
+        let envelope = NetworkEnvelope {
+            message_version: self.inner.state.read().await.message_version.clone(),
+            message: Some(EnvMsg::CloseConnectionMessage(CloseConnectionMessage {
+                reason: reason.to_string(),
+            })),
+        };
+        // Nothing to tell a peer that is already gone
+        let _ = self.send(envelope).await;
+        self.close();
+    }
+
+    async fn read_loop<R: AsyncRead + Unpin>(self, mut stream: EnvelopeStream<R>, router: Arc<P2PMessageRouter>) {
+        loop {
+            let envelope = tokio::select! {
+                _ = self.inner.closed.cancelled() => break,
+                read = stream.read_envelope() => match read {
+                    Ok(Some(envelope)) => envelope,
+                    Ok(None) => break,
+                    Err(e) => {
+                        if e.is_protocol_violation() {
+                            eprintln!("🚫 Connection {} sent an invalid frame: {e}", self.inner.id);
+                        } else {
+                            eprintln!("❌ Connection {} read failed: {e}", self.inner.id);
+                        }
+                        break;
+                    }
+                },
+            };
+
+            self.inner.state.write().await.observe(&envelope);
+
+            if let Err(err) = router.dispatch(self.clone(), envelope).await {
+                eprintln!("❌ Router error on connection {}: {err}", self.inner.id);
+            }
+        }
+
+        self.close();
+    }
+
+    async fn write_loop<W: AsyncWrite + Unpin>(
+        self,
+        mut stream: EnvelopeStream<W>,
+        mut outbound: mpsc::Receiver<NetworkEnvelope>,
+    ) {
+        loop {
+            let envelope = tokio::select! {
+                biased;
+                envelope = outbound.recv() => match envelope {
+                    Some(envelope) => envelope,
+                    None => break,
+                },
+                _ = self.inner.closed.cancelled() => break,
+            };
+
+            if let Err(e) = stream.write_envelope(&envelope).await {
+                eprintln!("❌ Connection {} write failed: {e}", self.inner.id);
+                self.close();
+                return;
+            }
+        }
+
+        // Whatever was queued before the close still goes out
+        outbound.close();
+        while let Ok(envelope) = outbound.try_recv() {
+            if stream.write_envelope(&envelope).await.is_err() {
+                break;
+            }
+        }
+        let _ = stream.get_mut().shutdown().await;
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::{Ping, Pong, PreliminaryGetDataRequest};
+    use crate::p2p::context::PeerContext;
+    use crate::p2p::router::P2PMessageHandler;
+    use async_trait::async_trait;
+
+    struct PongHandler;
+
+    #[async_trait]
+    impl P2PMessageHandler<Ping> for PongHandler {
+        async fn handle(&self, ctx: PeerContext, ping: Ping) -> Result<()> {
+            ctx.connection.send(envelope(EnvMsg::Pong(Pong { request_nonce: ping.nonce }))).await
+        }
+    }
+
+    fn envelope(message: EnvMsg) -> NetworkEnvelope {
+        NetworkEnvelope {
+            message_version: "0X".into(),
+            message: Some(message),
+        }
+    }
+
+    async fn connect() -> (Connection, EnvelopeStream<tokio::io::DuplexStream>) {
+        let router = Arc::new(P2PMessageRouter::new());
+        router.register(PongHandler).await;
+
+        let (local, remote) = tokio::io::duplex(1024);
+        let connection = Connection::spawn(local, None, CodecConfig::default(), router);
+        (connection, EnvelopeStream::new(remote))
+    }
+
+    #[tokio::test]
+    async fn handlers_reply_over_the_same_connection() {
+        let (connection, mut peer) = connect().await;
+
+        for nonce in 1..=3 {
+            peer.write_envelope(&envelope(EnvMsg::Ping(Ping { nonce, last_round_trip_time: 0 }))).await.unwrap();
+            let reply = peer.read_envelope().await.unwrap();
+            assert_eq!(reply, Some(envelope(EnvMsg::Pong(Pong { request_nonce: nonce }))));
+        }
+        assert!(!connection.is_closed());
+    }
+
+    #[tokio::test]
+    async fn records_peer_capabilities() {
+        let (connection, mut peer) = connect().await;
+
+        let request = PreliminaryGetDataRequest {
+            nonce: 1,
+            excluded_keys: vec![],
+            supported_capabilities: vec![11, 14],
+            version: "1.1.2".into(),
+        };
+        peer.write_envelope(&envelope(EnvMsg::PreliminaryGetDataRequest(request))).await.unwrap();
+        // Round trip a ping so the request has been processed
+        peer.write_envelope(&envelope(EnvMsg::Ping(Ping { nonce: 1, last_round_trip_time: 0 }))).await.unwrap();
+        peer.read_envelope().await.unwrap();
+
+        assert_eq!(connection.state().await.capabilities, vec![11, 14]);
+    }
+
+    #[tokio::test]
+    async fn close_flushes_queue_and_ends_stream() {
+        let (connection, mut peer) = connect().await;
+
+        let pong = envelope(EnvMsg::Pong(Pong { request_nonce: 9 }));
+        connection.send(pong.clone()).await.unwrap();
+        connection.close();
+
+        assert_eq!(peer.read_envelope().await.unwrap(), Some(pong));
+        assert_eq!(peer.read_envelope().await.unwrap(), None);
+        assert!(connection.send(envelope(EnvMsg::Pong(Pong { request_nonce: 10 }))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn close_with_reason_notifies_peer() {
+        let (connection, mut peer) = connect().await;
+
+        connection.close_with_reason("APP_SHUT_DOWN").await;
+        connection.finished().await;
+
+        let close = envelope(EnvMsg::CloseConnectionMessage(CloseConnectionMessage {
+            reason: "APP_SHUT_DOWN".into(),
+        }));
+        // No envelope from the peer yet, so there is no message_version to echo
+        let close = NetworkEnvelope { message_version: String::new(), ..close };
+        assert_eq!(peer.read_envelope().await.unwrap(), Some(close));
+        assert_eq!(peer.read_envelope().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn peer_hangup_closes_connection() {
+        let (connection, peer) = connect().await;
+        drop(peer);
+        connection.closed().await;
+        assert!(connection.is_closed());
+    }
+}
@@ -0,0 +1,315 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use crate::generated::io_haveno_protobuffer::NetworkEnvelope;
+use crate::p2p::connection::{Connection, ConnectionState};
+use crate::p2p::context::PeerContext;
+use crate::p2p::middleware::{MiddlewareAction, P2PMiddleware};
+use crate::utils::network::envelope::EnvMsg;
+
+/// `CloseConnectionReason.TOO_MANY_CONNECTIONS_OPEN` in Haveno
+const EVICTION_REASON: &str = "TOO_MANY_CONNECTIONS_OPEN";
+
+/// Haveno's `PeerType`: why a connection is open
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerType {
+    /// Seed node connections and peers doing their initial data exchange with us
+    Seed,
+    /// Regular network peers, the first to go when we are full
+    Peer,
+    /// Connections carrying direct (sealed) messages, usually for an ongoing trade
+    DirectMessage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionDirection {
+    Inbound,
+    Outbound,
+}
+
+/// A row of the connection table
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub peer_type: PeerType,
+    pub direction: ConnectionDirection,
+    pub state: ConnectionState,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionManagerConfig {
+    pub max_connections: usize,
+}
+
+impl Default for ConnectionManagerConfig {
+    /// Haveno's default `maxConnections`
+    fn default() -> Self {
+        Self { max_connections: 12 }
+    }
+}
+
+impl ConnectionManagerConfig {
+    /// Above this, outbound peers can be evicted too
+    fn max_connections_peer(&self) -> usize {
+        scaled(self.max_connections, 1.3).max(4)
+    }
+
+    /// Above this, seed connections can be evicted too
+    fn max_connections_non_direct(&self) -> usize {
+        scaled(self.max_connections, 1.7).max(8)
+    }
+
+    /// Above this, anything goes, direct message connections included
+    fn max_connections_absolute(&self) -> usize {
+        scaled(self.max_connections, 2.5).max(12)
+    }
+}
+
+fn scaled(max_connections: usize, factor: f64) -> usize {
+    (max_connections as f64 * factor).round() as usize
+}
+
+struct ManagedConnection {
+    connection: Connection,
+    peer_type: PeerType,
+    direction: ConnectionDirection,
+}
+
+/// Keeps the number of open connections within limits, the way Haveno's `PeerManager` does.
+///
+/// When over the limit, the least recently active connection of the least useful kind is
+/// closed: inbound peers first, then any peer, then seed connections, and direct message
+/// connections only as a last resort. Add it to the router as middleware so connections get
+/// reclassified from the messages they carry.
+#[derive(Default)]
+pub struct ConnectionManager {
+    config: ConnectionManagerConfig,
+    connections: RwLock<HashMap<u64, ManagedConnection>>,
+}
+
+impl ConnectionManager {
+    pub fn new(config: ConnectionManagerConfig) -> Self {
+        Self {
+            config,
+            connections: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Start managing `connection`, evicting others if that puts us over the limit
+    pub async fn add(&self, connection: Connection, peer_type: PeerType, direction: ConnectionDirection) {
+        self.connections.write().await.insert(
+            connection.id(),
+            ManagedConnection {
+                connection,
+                peer_type,
+                direction,
+            },
+        );
+        self.enforce_limits().await;
+    }
+
+    pub async fn set_peer_type(&self, id: u64, peer_type: PeerType) {
+        if let Some(managed) = self.connections.write().await.get_mut(&id) {
+            managed.peer_type = peer_type;
+        }
+    }
+
+    /// The open connections, closed ones are dropped from the table as a side effect
+    pub async fn connections(&self) -> Vec<ConnectionInfo> {
+        self.prune().await;
+
+        let connections = self.connections.read().await;
+        let mut table = Vec::with_capacity(connections.len());
+        for (id, managed) in connections.iter() {
+            table.push(ConnectionInfo {
+                id: *id,
+                peer_type: managed.peer_type,
+                direction: managed.direction,
+                state: managed.connection.state().await,
+            });
+        }
+        table.sort_by_key(|info| info.id);
+        table
+    }
+
+    /// Send `envelope` to every open connection except direct message ones, which are
+    /// private to a single peer. Returns how many peers it was queued for.
+    pub async fn broadcast(&self, envelope: &NetworkEnvelope) -> usize {
+        self.prune().await;
+
+        let connections: Vec<Connection> = self
+            .connections
+            .read()
+            .await
+            .values()
+            .filter(|managed| managed.peer_type != PeerType::DirectMessage)
+            .map(|managed| managed.connection.clone())
+            .collect();
+        let mut sent = 0;
+        for connection in connections {
+            match connection.send(envelope.clone()).await {
+                Ok(()) => sent += 1,
+                Err(e) => eprintln!("❌ Broadcast to connection {} failed: {e}", connection.id()),
+            }
+        }
+        sent
+    }
+
+    pub async fn len(&self) -> usize {
+        self.prune().await;
+        self.connections.read().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    async fn prune(&self) {
+        self.connections.write().await.retain(|_, managed| !managed.connection.is_closed());
+    }
+
+    async fn enforce_limits(&self) {
+        self.prune().await;
+        while let Some(connection) = self.eviction_candidate().await {
+            println!("✂️ Too many connections, closing connection {}", connection.id());
+            self.connections.write().await.remove(&connection.id());
+            connection.close_with_reason(EVICTION_REASON).await;
+        }
+    }
+
+    /// The connection to close next, or `None` if we are within limits
+    async fn eviction_candidate(&self) -> Option<Connection> {
+        let connections = self.connections.read().await;
+        let size = connections.len();
+        if size <= self.config.max_connections {
+            return None;
+        }
+
+        // Each tier only widens the net if the previous one found nothing
+        let tiers: [(usize, fn(&ManagedConnection) -> bool); 4] = [
+            (self.config.max_connections, |m| {
+                m.peer_type == PeerType::Peer && m.direction == ConnectionDirection::Inbound
+            }),
+            (self.config.max_connections_peer(), |m| m.peer_type == PeerType::Peer),
+            (self.config.max_connections_non_direct(), |m| m.peer_type != PeerType::DirectMessage),
+            (self.config.max_connections_absolute(), |_| true),
+        ];
+
+        for (limit, is_candidate) in tiers {
+            if size <= limit {
+                return None;
+            }
+
+            let mut candidate = None;
+            for managed in connections.values().filter(|m| is_candidate(m)) {
+                let last_activity = managed.connection.state().await.last_activity;
+                if candidate.as_ref().is_none_or(|(oldest, _)| last_activity < *oldest) {
+                    candidate = Some((last_activity, managed.connection.clone()));
+                }
+            }
+            if let Some((_, connection)) = candidate {
+                return Some(connection);
+            }
+        }
+        None
+    }
+}
+
+#[async_trait]
+impl P2PMiddleware for ConnectionManager {
+    async fn before(&self, ctx: &mut PeerContext, message: &EnvMsg) -> MiddlewareAction {
+        let peer_type = match message {
+            EnvMsg::PreliminaryGetDataRequest(_) | EnvMsg::GetDataResponse(_) => PeerType::Seed,
+            EnvMsg::PrefixedSealedAndSignedMessage(_) => PeerType::DirectMessage,
+            _ => return MiddlewareAction::Continue,
+        };
+
+        // A connection that carried a direct message stays one
+        let mut connections = self.connections.write().await;
+        if let Some(managed) = connections.get_mut(&ctx.connection.id()) {
+            if managed.peer_type != PeerType::DirectMessage {
+                managed.peer_type = peer_type;
+            }
+        }
+        MiddlewareAction::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::p2p::router::P2PMessageRouter;
+    use crate::utils::network::codec::CodecConfig;
+    use std::sync::Arc;
+    use tokio::io::DuplexStream;
+    use tokio::time::{Duration, Instant};
+
+    /// A connection last active `age` seconds before `now`
+    async fn spawn_connection(now: Instant, age: u64) -> (Connection, DuplexStream) {
+        let (local, remote) = tokio::io::duplex(1024);
+        let connection = Connection::spawn(local, None, CodecConfig::default(), Arc::new(P2PMessageRouter::new()));
+        connection.update_state(|state| state.last_activity = now - Duration::from_secs(age)).await;
+        (connection, remote)
+    }
+
+    #[tokio::test]
+    async fn evicts_inbound_peers_first() {
+        let now = Instant::now();
+        let manager = ConnectionManager::new(ConnectionManagerConfig { max_connections: 2 });
+        let mut peers = Vec::new();
+
+        let (seed, remote) = spawn_connection(now, 60).await;
+        peers.push(remote);
+        manager.add(seed.clone(), PeerType::Seed, ConnectionDirection::Inbound).await;
+
+        let (outbound, remote) = spawn_connection(now, 50).await;
+        peers.push(remote);
+        manager.add(outbound.clone(), PeerType::Peer, ConnectionDirection::Outbound).await;
+
+        let mut inbound = Vec::new();
+        for age in [10, 5] {
+            let (connection, remote) = spawn_connection(now, age).await;
+            peers.push(remote);
+            inbound.push(connection);
+        }
+        manager.add(inbound[0].clone(), PeerType::Peer, ConnectionDirection::Inbound).await;
+        assert!(inbound[0].is_closed());
+        assert_eq!(manager.len().await, 2);
+
+        // The newer inbound peer is evicted before the seed and the outbound peer,
+        // even though those have been idle for longer
+        manager.add(inbound[1].clone(), PeerType::Peer, ConnectionDirection::Inbound).await;
+        assert!(inbound[1].is_closed());
+        assert!(!seed.is_closed());
+        assert!(!outbound.is_closed());
+    }
+
+    #[tokio::test]
+    async fn spares_direct_message_connections() {
+        let now = Instant::now();
+        let manager = ConnectionManager::new(ConnectionManagerConfig { max_connections: 1 });
+        let mut peers = Vec::new();
+
+        let (direct, remote) = spawn_connection(now, 100).await;
+        peers.push(remote);
+        manager.add(direct.clone(), PeerType::DirectMessage, ConnectionDirection::Outbound).await;
+
+        // With max 1 the peer limit is 4, so outbound peers are only evicted beyond that,
+        // oldest first
+        let mut outbound = Vec::new();
+        for age in [40, 30, 20, 10] {
+            let (connection, remote) = spawn_connection(now, age).await;
+            peers.push(remote);
+            manager.add(connection.clone(), PeerType::Peer, ConnectionDirection::Outbound).await;
+            outbound.push(connection);
+        }
+
+        assert_eq!(manager.len().await, 4);
+        assert!(!direct.is_closed());
+        assert!(outbound[0].is_closed());
+        assert!(outbound[1..].iter().all(|c| !c.is_closed()));
+
+        let table = manager.connections().await;
+        assert_eq!(table[0].peer_type, PeerType::DirectMessage);
+    }
+}
@@ -1,7 +1,47 @@
+use anyhow::Result;
+use std::collections::HashMap;
 use crate::generated::io_haveno_protobuffer::NetworkEnvelope;
+use crate::p2p::connection::Connection;
+use crate::utils::network::ack::AckSource;
+use crate::utils::network::message::EnvelopeMessage;
 
 /// Holds context about a received P2P message and connection
+#[derive(Clone)]
 pub struct PeerContext {
-    pub stream: tokio::net::TcpStream,
-    pub envelope: NetworkEnvelope,
+    /// The connection the message arrived on, also the way to answer it
+    pub connection: Connection,
+    /// `message_version` of the envelope the message came in
+    pub message_version: String,
+    /// Notes left by middleware for later middleware and the handler
+    pub annotations: HashMap<&'static str, String>,
+    /// Set when the message is one Haveno expects an `AckMessage` for
+    pub ack_source: Option<AckSource>,
+}
+
+impl PeerContext {
+    pub fn new(connection: Connection, message_version: String) -> Self {
+        Self {
+            connection,
+            message_version,
+            annotations: HashMap::new(),
+            ack_source: None,
+        }
+    }
+
+    /// Send `message` back to the peer, in the same `message_version` it wrote to us
+    pub async fn reply<M: EnvelopeMessage>(&self, message: M) -> Result<()> {
+        let envelope = NetworkEnvelope {
+            message_version: self.message_version.clone(),
+            message: Some(message.into_envelope_message()),
+        };
+        self.connection.send(envelope).await
+    }
+
+    pub fn annotate(&mut self, key: &'static str, value: impl Into<String>) {
+        self.annotations.insert(key, value.into());
+    }
+
+    pub fn annotation(&self, key: &str) -> Option<&str> {
+        self.annotations.get(key).map(String::as_str)
+    }
 }
\ No newline at end of file
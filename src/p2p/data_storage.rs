@@ -0,0 +1,496 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use crate::generated::io_haveno_protobuffer::{
+    persistable_envelope::Message as PersistableMessage, storage_entry_wrapper::Message as WrapperMessage,
+    storage_payload::Message as PayloadMessage, ByteArray, MapValue, PersistableEnvelope,
+    ProtectedMailboxStorageEntry, ProtectedStorageEntry, RefreshOfferMessage, SequenceNumberEntry, SequenceNumberMap,
+};
+use crate::p2p::protected_entry::{
+    check_sequence_number, payload_owner_key, time_to_live, validate_mailbox_entry, validate_protected_entry,
+    verify_owner_signature, EntryError,
+};
+use crate::utils::storage::persistence_manager::{read_persistable_envelope, write_persistable_envelope};
+
+/// SHA-256 of the serialized `StoragePayload`, what entries are keyed by
+pub type PayloadHash = [u8; 32];
+
+/// How many events a slow subscriber may fall behind before it starts missing them
+const EVENT_CAPACITY: usize = 1024;
+
+/// Sequence numbers not touched for this long are dropped on load, Haveno's `PURGE_AGE_DAYS`.
+/// Longer than any entry lives, so whatever they guarded against has expired anyway.
+const SEQUENCE_NUMBER_PURGE_AGE: Duration = Duration::from_secs(10 * 24 * 60 * 60);
+
+/// File name Haveno persists the sequence numbers under in its `db` directory
+const SEQUENCE_NUMBER_FILE: &str = "SequenceNumberMap";
+
+/// A change to the stored entries, for the offer book, filter manager and the like
+#[derive(Debug, Clone)]
+pub enum StorageEvent {
+    Added { hash: PayloadHash, entry: WrapperMessage },
+    Removed { hash: PayloadHash, entry: WrapperMessage },
+    Refreshed { hash: PayloadHash, sequence_number: i32 },
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<PayloadHash, WrapperMessage>,
+    /// Highest sequence number seen by payload hash, and when. Kept after a removal, and
+    /// across restarts, so the removed version can't be added again.
+    sequence_numbers: HashMap<PayloadHash, MapValue>,
+    /// Whether `sequence_numbers` changed since they were last persisted
+    dirty: bool,
+}
+
+impl Inner {
+    /// `Ok(false)` for a sequence number we've already seen, which peers relaying the same
+    /// message to us is the usual cause of, and an error for an older one
+    fn is_new(&self, hash: &PayloadHash, received: i32) -> Result<bool, EntryError> {
+        let known = self.sequence_numbers.get(hash).map(|value| value.sequence_nr);
+        if known == Some(received) {
+            return Ok(false);
+        }
+        check_sequence_number(received, known)?;
+        Ok(true)
+    }
+
+    fn record(&mut self, hash: PayloadHash, sequence_nr: i32, now_millis: i64) {
+        self.sequence_numbers.insert(hash, MapValue { sequence_nr, time_stamp: now_millis });
+        self.dirty = true;
+    }
+}
+
+/// The protected storage entries we know of, Haveno's `P2PDataStorage` map: offers, filters,
+/// alerts, dispute agents and mailbox messages, keyed by payload hash.
+///
+/// Entries only change under their owner's signature and with an increasing sequence number.
+pub struct P2PDataStorage {
+    inner: RwLock<Inner>,
+    events: broadcast::Sender<StorageEvent>,
+    /// Where `persist` writes the sequence numbers, if anywhere
+    sequence_number_file: Option<PathBuf>,
+}
+
+impl Default for P2PDataStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl P2PDataStorage {
+    pub fn new() -> Self {
+        Self {
+            inner: RwLock::new(Inner::default()),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            sequence_number_file: None,
+        }
+    }
+
+    /// Storage that keeps its sequence numbers in `db_dir`, loading the ones persisted there.
+    /// Numbers older than `SEQUENCE_NUMBER_PURGE_AGE` are dropped.
+    pub fn open(db_dir: &Path, now_millis: i64) -> Result<Self> {
+        let path = db_dir.join(SEQUENCE_NUMBER_FILE);
+        let mut sequence_numbers = if path.exists() {
+            load_sequence_numbers(&path)?
+        } else {
+            HashMap::new()
+        };
+
+        let loaded = sequence_numbers.len();
+        let cutoff = now_millis - SEQUENCE_NUMBER_PURGE_AGE.as_millis() as i64;
+        sequence_numbers.retain(|_, value| value.time_stamp >= cutoff);
+        println!(
+            "💾 Loaded {} sequence numbers from {}, purged {}",
+            sequence_numbers.len(),
+            path.display(),
+            loaded - sequence_numbers.len()
+        );
+
+        Ok(Self {
+            inner: RwLock::new(Inner {
+                entries: HashMap::new(),
+                dirty: sequence_numbers.len() != loaded,
+                sequence_numbers,
+            }),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            sequence_number_file: Some(path),
+        })
+    }
+
+    /// Write the sequence numbers if they changed since the last write.
+    /// A no-op for storage made with `new`.
+    pub async fn persist(&self) -> Result<()> {
+        let Some(path) = &self.sequence_number_file else {
+            return Ok(());
+        };
+        let mut inner = self.inner.write().await;
+        if !inner.dirty {
+            return Ok(());
+        }
+        save_sequence_numbers(path, &inner.sequence_numbers)?;
+        inner.dirty = false;
+        Ok(())
+    }
+
+    /// Receive every change made from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn get(&self, hash: &PayloadHash) -> Option<WrapperMessage> {
+        self.inner.read().await.entries.get(hash).cloned()
+    }
+
+    pub async fn entries(&self) -> Vec<(PayloadHash, WrapperMessage)> {
+        self.inner.read().await.entries.iter().map(|(hash, entry)| (*hash, entry.clone())).collect()
+    }
+
+    pub async fn hashes(&self) -> Vec<PayloadHash> {
+        self.inner.read().await.entries.keys().copied().collect()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.inner.read().await.entries.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Add or update an entry, from an `AddDataMessage`.
+    /// Returns whether it changed anything; a version we already have is not an error.
+    pub async fn add(&self, entry: WrapperMessage, now_millis: i64) -> Result<bool, EntryError> {
+        let (hash, sequence_number) = match &entry {
+            WrapperMessage::ProtectedStorageEntry(protected) => {
+                (validate_protected_entry(protected, now_millis)?, protected.sequence_number)
+            }
+            WrapperMessage::ProtectedMailboxStorageEntry(mailbox) => {
+                let sequence_number = mailbox.entry.as_ref().map_or(0, |inner| inner.sequence_number);
+                (validate_mailbox_entry(mailbox, now_millis)?, sequence_number)
+            }
+        };
+
+        let mut inner = self.inner.write().await;
+        if !inner.is_new(&hash, sequence_number)? {
+            return Ok(false);
+        }
+        // An update must come from whoever added the entry
+        if let Some(stored) = inner.entries.get(&hash) {
+            if relevant_owner(stored) != relevant_owner(&entry) {
+                return Err(EntryError::OwnerMismatch);
+            }
+        }
+
+        inner.record(hash, sequence_number, now_millis);
+        inner.entries.insert(hash, entry.clone());
+        let _ = self.events.send(StorageEvent::Added { hash, entry });
+        Ok(true)
+    }
+
+    /// Remove an entry for a `RemoveDataMessage`, which its owner signs at a higher sequence
+    /// number. Returns whether we had it; the sequence number is recorded either way, so a
+    /// late add of the removed version is refused.
+    pub async fn remove(&self, entry: ProtectedStorageEntry, now_millis: i64) -> Result<bool, EntryError> {
+        let payload = entry.storage_payload.as_ref().ok_or(EntryError::MissingPayload)?;
+        if matches!(payload.message, Some(PayloadMessage::MailboxStoragePayload(_))) {
+            // Mailbox entries are only removed by their receiver, through `remove_mailbox`
+            return Err(EntryError::ReceiverMismatch);
+        }
+        let owner = payload_owner_key(payload).ok_or(EntryError::MissingOwnerKey)?;
+        let hash = verify_owner_signature(&entry, owner)?;
+        self.remove_verified(hash, entry.sequence_number, &entry.owner_pub_key_bytes, now_millis).await
+    }
+
+    /// Remove a mailbox entry for a `RemoveMailboxDataMessage`. Only the receiver, who owns
+    /// the payload, may remove it once they've read it.
+    pub async fn remove_mailbox(&self, entry: ProtectedMailboxStorageEntry, now_millis: i64) -> Result<bool, EntryError> {
+        let inner = entry.entry.as_ref().ok_or(EntryError::MissingPayload)?;
+        let Some(PayloadMessage::MailboxStoragePayload(mailbox)) = inner.storage_payload.as_ref().and_then(|p| p.message.as_ref()) else {
+            return Err(EntryError::MissingPayload);
+        };
+        if mailbox.owner_pub_key_bytes.is_empty() {
+            return Err(EntryError::MissingOwnerKey);
+        }
+        if entry.receivers_pub_key_bytes != mailbox.owner_pub_key_bytes {
+            return Err(EntryError::ReceiverMismatch);
+        }
+        let hash = verify_owner_signature(inner, &mailbox.owner_pub_key_bytes)?;
+        self.remove_verified(hash, inner.sequence_number, &entry.receivers_pub_key_bytes, now_millis).await
+    }
+
+    async fn remove_verified(&self, hash: PayloadHash, sequence_number: i32, owner: &[u8], now_millis: i64) -> Result<bool, EntryError> {
+        let mut inner = self.inner.write().await;
+        if !inner.is_new(&hash, sequence_number)? {
+            return Ok(false);
+        }
+        if let Some(stored) = inner.entries.get(&hash) {
+            if relevant_owner(stored) != owner {
+                return Err(EntryError::OwnerMismatch);
+            }
+        }
+
+        inner.record(hash, sequence_number, now_millis);
+        let Some(entry) = inner.entries.remove(&hash) else {
+            return Ok(false);
+        };
+        let _ = self.events.send(StorageEvent::Removed { hash, entry });
+        Ok(true)
+    }
+
+    /// Keep an offer alive for a `RefreshOfferMessage`, which carries only the owner's
+    /// signature over the payload at the new sequence number. The creation time restarts.
+    pub async fn refresh(&self, message: RefreshOfferMessage, now_millis: i64) -> Result<bool, EntryError> {
+        let hash: PayloadHash = message.hash_of_payload.as_slice().try_into().map_err(|_| EntryError::UnknownEntry)?;
+
+        let mut inner = self.inner.write().await;
+        let Some(WrapperMessage::ProtectedStorageEntry(stored)) = inner.entries.get(&hash) else {
+            return Err(EntryError::UnknownEntry);
+        };
+        let refreshed = ProtectedStorageEntry {
+            storage_payload: stored.storage_payload.clone(),
+            owner_pub_key_bytes: stored.owner_pub_key_bytes.clone(),
+            sequence_number: message.sequence_number,
+            signature: message.signature,
+            creation_time_stamp: now_millis,
+        };
+        if !inner.is_new(&hash, refreshed.sequence_number)? {
+            return Ok(false);
+        }
+        verify_owner_signature(&refreshed, &refreshed.owner_pub_key_bytes)?;
+
+        let sequence_number = refreshed.sequence_number;
+        inner.record(hash, sequence_number, now_millis);
+        inner.entries.insert(hash, WrapperMessage::ProtectedStorageEntry(refreshed));
+        let _ = self.events.send(StorageEvent::Refreshed { hash, sequence_number });
+        Ok(true)
+    }
+
+    /// Drop entries that outlived their payload type's time to live without a refresh.
+    /// Returns how many were removed.
+    pub async fn remove_expired(&self, now_millis: i64) -> usize {
+        let mut inner = self.inner.write().await;
+        let expired: Vec<PayloadHash> = inner
+            .entries
+            .iter()
+            .filter(|(_, entry)| is_expired(entry, now_millis))
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        for hash in &expired {
+            if let Some(entry) = inner.entries.remove(hash) {
+                let _ = self.events.send(StorageEvent::Removed { hash: *hash, entry });
+            }
+        }
+        expired.len()
+    }
+}
+
+fn load_sequence_numbers(path: &Path) -> Result<HashMap<PayloadHash, MapValue>> {
+    let Some(PersistableMessage::SequenceNumberMap(map)) = read_persistable_envelope(path)?.message else {
+        bail!("{} doesn't hold a SequenceNumberMap", path.display());
+    };
+
+    let mut sequence_numbers = HashMap::new();
+    for entry in map.sequence_number_entries {
+        let (Some(bytes), Some(value)) = (entry.bytes, entry.map_value) else {
+            continue;
+        };
+        if let Ok(hash) = PayloadHash::try_from(bytes.bytes.as_slice()) {
+            sequence_numbers.insert(hash, value);
+        }
+    }
+    Ok(sequence_numbers)
+}
+
+fn save_sequence_numbers(path: &Path, sequence_numbers: &HashMap<PayloadHash, MapValue>) -> Result<()> {
+    let map = SequenceNumberMap {
+        sequence_number_entries: sequence_numbers
+            .iter()
+            .map(|(hash, value)| SequenceNumberEntry {
+                bytes: Some(ByteArray { bytes: hash.to_vec() }),
+                map_value: Some(*value),
+            })
+            .collect(),
+    };
+    write_persistable_envelope(path, &PersistableEnvelope {
+        message: Some(PersistableMessage::SequenceNumberMap(map)),
+    })
+}
+
+/// The key that may change an entry, Haveno's `matchesRelevantPubKey`: the owner of a protected
+/// entry, and the receiver of a mailbox entry rather than its sender
+fn relevant_owner(entry: &WrapperMessage) -> &[u8] {
+    match entry {
+        WrapperMessage::ProtectedStorageEntry(protected) => &protected.owner_pub_key_bytes,
+        WrapperMessage::ProtectedMailboxStorageEntry(mailbox) => &mailbox.receivers_pub_key_bytes,
+    }
+}
+
+fn is_expired(entry: &WrapperMessage, now_millis: i64) -> bool {
+    let protected = match entry {
+        WrapperMessage::ProtectedStorageEntry(protected) => protected,
+        WrapperMessage::ProtectedMailboxStorageEntry(mailbox) => match &mailbox.entry {
+            Some(inner) => inner,
+            None => return true,
+        },
+    };
+    let Some(payload) = &protected.storage_payload else {
+        return true;
+    };
+    let age = now_millis - protected.creation_time_stamp;
+    age > 0 && Duration::from_millis(age as u64) > time_to_live(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::{add_data, filter};
+    use crate::crypto::key_ring::KeyRing;
+    use crate::generated::io_haveno_protobuffer::StoragePayload;
+    use crate::p2p::protected_entry::{payload_hash, signed_hash};
+
+    fn filter_entry(key_ring: &KeyRing) -> ProtectedStorageEntry {
+        let filter = filter::build_signed_filter(key_ring).unwrap();
+        let message = add_data::build_add_data_message(PayloadMessage::Filter(filter), key_ring).unwrap();
+        match message.entry.unwrap().message.unwrap() {
+            WrapperMessage::ProtectedStorageEntry(entry) => entry,
+            other => panic!("unexpected entry {other:?}"),
+        }
+    }
+
+    /// `entry` re-signed by `key_ring` at `sequence_number`
+    fn resigned(entry: &ProtectedStorageEntry, sequence_number: i32, key_ring: &KeyRing) -> ProtectedStorageEntry {
+        let payload = entry.storage_payload.as_ref().unwrap();
+        ProtectedStorageEntry {
+            sequence_number,
+            signature: add_data::sign_storage_payload(payload, sequence_number, key_ring).unwrap(),
+            owner_pub_key_bytes: key_ring.signature().public_key_der().unwrap(),
+            ..entry.clone()
+        }
+    }
+
+    fn hash_of(entry: &ProtectedStorageEntry) -> PayloadHash {
+        payload_hash(entry.storage_payload.as_ref().unwrap())
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+
+    #[tokio::test]
+    async fn adds_new_versions_only() {
+        let key_ring = KeyRing::generate().unwrap();
+        let storage = P2PDataStorage::new();
+        let mut events = storage.subscribe();
+        let entry = filter_entry(&key_ring);
+
+        assert_eq!(storage.add(WrapperMessage::ProtectedStorageEntry(entry.clone()), now()).await, Ok(true));
+        assert!(matches!(events.try_recv(), Ok(StorageEvent::Added { hash, .. }) if hash == hash_of(&entry)));
+
+        // A relayed duplicate changes nothing
+        assert_eq!(storage.add(WrapperMessage::ProtectedStorageEntry(entry.clone()), now()).await, Ok(false));
+        assert!(events.try_recv().is_err());
+
+        let update = resigned(&entry, 3, &key_ring);
+        assert_eq!(storage.add(WrapperMessage::ProtectedStorageEntry(update), now()).await, Ok(true));
+        let stale = resigned(&entry, 2, &key_ring);
+        assert_eq!(
+            storage.add(WrapperMessage::ProtectedStorageEntry(stale), now()).await,
+            Err(EntryError::StaleSequenceNumber { received: 2, known: 3 })
+        );
+        assert_eq!(storage.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_removes() {
+        let key_ring = KeyRing::generate().unwrap();
+        let storage = P2PDataStorage::new();
+        let entry = filter_entry(&key_ring);
+        storage.add(WrapperMessage::ProtectedStorageEntry(entry.clone()), now()).await.unwrap();
+
+        let intruder = KeyRing::generate().unwrap();
+        assert_eq!(storage.remove(resigned(&entry, 2, &intruder), now()).await, Err(EntryError::OwnerMismatch));
+        let mut forged = resigned(&entry, 2, &key_ring);
+        forged.sequence_number = 5;
+        assert_eq!(storage.remove(forged, now()).await, Err(EntryError::InvalidSignature));
+        assert!(storage.get(&hash_of(&entry)).await.is_some());
+
+        let mut events = storage.subscribe();
+        assert_eq!(storage.remove(resigned(&entry, 2, &key_ring), now()).await, Ok(true));
+        assert!(matches!(events.try_recv(), Ok(StorageEvent::Removed { .. })));
+        assert!(storage.is_empty().await);
+
+        // The removed version can't come back
+        assert!(storage.add(WrapperMessage::ProtectedStorageEntry(entry), now()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refreshes_with_the_owner_signature() {
+        let key_ring = KeyRing::generate().unwrap();
+        let storage = P2PDataStorage::new();
+        let entry = filter_entry(&key_ring);
+        let hash = hash_of(&entry);
+        storage.add(WrapperMessage::ProtectedStorageEntry(entry.clone()), now()).await.unwrap();
+
+        let payload: &StoragePayload = entry.storage_payload.as_ref().unwrap();
+        let refresh = |sequence_number: i32, key_ring: &KeyRing| RefreshOfferMessage {
+            hash_of_data_and_seq_nr: signed_hash(payload, sequence_number).to_vec(),
+            signature: add_data::sign_storage_payload(payload, sequence_number, key_ring).unwrap(),
+            hash_of_payload: hash.to_vec(),
+            sequence_number,
+        };
+
+        let intruder = KeyRing::generate().unwrap();
+        assert_eq!(storage.refresh(refresh(2, &intruder), now()).await, Err(EntryError::InvalidSignature));
+
+        let later = now() + 60_000;
+        assert_eq!(storage.refresh(refresh(2, &key_ring), later).await, Ok(true));
+        let Some(WrapperMessage::ProtectedStorageEntry(refreshed)) = storage.get(&hash).await else {
+            panic!("refreshed entry missing");
+        };
+        assert_eq!((refreshed.sequence_number, refreshed.creation_time_stamp), (2, later));
+
+        let mut unknown = refresh(3, &key_ring);
+        unknown.hash_of_payload = vec![0; 32];
+        assert_eq!(storage.refresh(unknown, now()).await, Err(EntryError::UnknownEntry));
+    }
+
+    #[tokio::test]
+    async fn sequence_numbers_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("data-storage-test-{}", uuid::Uuid::new_v4()));
+        let key_ring = KeyRing::generate().unwrap();
+        let entry = filter_entry(&key_ring);
+
+        let storage = P2PDataStorage::open(&dir, now()).unwrap();
+        storage.add(WrapperMessage::ProtectedStorageEntry(entry.clone()), now()).await.unwrap();
+        storage.remove(resigned(&entry, 2, &key_ring), now()).await.unwrap();
+        storage.persist().await.unwrap();
+
+        // The removed entry replayed after a restart is refused
+        let reopened = P2PDataStorage::open(&dir, now()).unwrap();
+        assert_eq!(
+            reopened.add(WrapperMessage::ProtectedStorageEntry(entry.clone()), now()).await,
+            Err(EntryError::StaleSequenceNumber { received: 1, known: 2 })
+        );
+
+        // Until its sequence number is old enough to be purged
+        let purged = P2PDataStorage::open(&dir, now() + 11 * 24 * 60 * 60_000).unwrap();
+        assert_eq!(purged.add(WrapperMessage::ProtectedStorageEntry(entry), now()).await, Ok(true));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn drops_expired_entries() {
+        let storage = P2PDataStorage::new();
+        let entry = filter_entry(&KeyRing::generate().unwrap());
+        storage.add(WrapperMessage::ProtectedStorageEntry(entry.clone()), now()).await.unwrap();
+
+        assert_eq!(storage.remove_expired(now()).await, 0);
+        let after_ttl = entry.creation_time_stamp + 181 * 24 * 60 * 60_000;
+        assert_eq!(storage.remove_expired(after_ttl).await, 1);
+        assert!(storage.is_empty().await);
+    }
+}
@@ -0,0 +1,366 @@
+use async_trait::async_trait;
+use anyhow::{anyhow, bail, Context, Result};
+use openssl::sha::sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use tokio::sync::{oneshot, Mutex};
+use tokio::time::{timeout, Duration};
+use uuid::Uuid;
+use crate::builders::add_data::build_mailbox_add_data_message;
+use crate::crypto::key_ring::KeyRing;
+use crate::crypto::sealed::{seal, unseal};
+use crate::generated::io_haveno_protobuffer::{AckMessage, NetworkEnvelope, PrefixedSealedAndSignedMessage, PubKeyRing};
+use crate::p2p::address::NodeAddress;
+use crate::p2p::connection::Connection;
+use crate::p2p::connection_manager::{ConnectionDirection, ConnectionManager, PeerType};
+use crate::p2p::context::PeerContext;
+use crate::p2p::router::{P2PMessageHandler, P2PMessageRouter};
+use crate::p2p::transport::Transport;
+use crate::utils::network::ack::ack_source;
+use crate::utils::network::codec::CodecConfig;
+use crate::utils::network::envelope::EnvMsg;
+use crate::utils::network::message::{message_name, EnvelopeMessage};
+
+#[derive(Debug, Clone)]
+pub struct DirectMessageConfig {
+    /// How long reaching the peer may take before we treat it as offline
+    pub connect_timeout: Duration,
+    /// How long the peer has to ack a message before it goes to its mailbox instead
+    pub ack_timeout: Duration,
+}
+
+impl Default for DirectMessageConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(60),
+            ack_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// How a message reached its peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// The peer received and acked it
+    Acked,
+    /// The peer was unreachable, so the message was left in the network for it to pick up
+    StoredInMailbox,
+}
+
+/// `NodeAddress.getAddressPrefixHash` in Haveno: the SHA-256 of the first two characters of
+/// the receiver's address, which lets peers skip mailbox messages that can't be for them
+pub fn address_prefix_hash(address: &NodeAddress) -> [u8; 32] {
+    let full_address = address.to_string();
+    let prefix: String = full_address.chars().take(2).collect();
+    sha256(prefix.as_bytes())
+}
+
+struct DirectMessengerInner {
+    config: DirectMessageConfig,
+    transport: Arc<dyn Transport>,
+    connection_manager: Arc<ConnectionManager>,
+    /// Weak, as the router holds the messenger as a handler
+    router: Weak<P2PMessageRouter>,
+    key_ring: Arc<KeyRing>,
+    node_address: NodeAddress,
+    message_version: String,
+    /// Open direct connections, by peer address
+    peers: Mutex<HashMap<String, Connection>>,
+    /// Acks we are waiting on, by the `uid` of the message they acknowledge
+    pending_acks: Mutex<HashMap<String, oneshot::Sender<AckMessage>>>,
+}
+
+/// Sends sealed messages straight to a peer, falling back to its mailbox when it is offline,
+/// and opens the sealed messages peers send us.
+///
+/// Only message types Haveno acks can be sent, since the ack is how we know the peer got it.
+/// Register the messenger with the router its connections use, so acks and sealed messages
+/// reach it; opened messages are dispatched through that router like any other message.
+#[derive(Clone)]
+pub struct DirectMessenger {
+    inner: Arc<DirectMessengerInner>,
+}
+
+impl DirectMessenger {
+    /// `node_address` is our own address, which peers reply to
+    pub fn new(
+        config: DirectMessageConfig,
+        transport: Arc<dyn Transport>,
+        connection_manager: Arc<ConnectionManager>,
+        router: &Arc<P2PMessageRouter>,
+        key_ring: Arc<KeyRing>,
+        node_address: NodeAddress,
+        message_version: impl Into<String>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(DirectMessengerInner {
+                config,
+                transport,
+                connection_manager,
+                router: Arc::downgrade(router),
+                key_ring,
+                node_address,
+                message_version: message_version.into(),
+                peers: Mutex::new(HashMap::new()),
+                pending_acks: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Receive acks and sealed messages on `router`
+    pub async fn register(&self, router: &P2PMessageRouter) {
+        router.register::<AckMessage, _>(self.clone()).await;
+        router.register::<PrefixedSealedAndSignedMessage, _>(self.clone()).await;
+    }
+
+    /// Seal `message` for the peer at `peer` holding `peer_keys` and deliver it, to its
+    /// mailbox if it can't be reached or doesn't ack in time. A negative ack is an error.
+    pub async fn send<M: EnvelopeMessage>(&self, peer: &NodeAddress, peer_keys: &PubKeyRing, message: M) -> Result<Delivery> {
+        let message = message.into_envelope_message();
+        let name = message_name(&message);
+        let source = ack_source(&message).ok_or_else(|| anyhow!("{name} isn't acked, so it can't be sent directly"))?;
+
+        let envelope = NetworkEnvelope {
+            message_version: self.inner.message_version.clone(),
+            message: Some(message),
+        };
+        let prefixed = PrefixedSealedAndSignedMessage {
+            node_address: Some((&self.inner.node_address).into()),
+            sealed_and_signed: Some(seal(&envelope, peer_keys, &self.inner.key_ring)?),
+            address_prefix_hash: address_prefix_hash(peer).to_vec(),
+            uid: Uuid::new_v4().to_string(),
+        };
+
+        let (ack_sender, ack) = oneshot::channel();
+        self.inner.pending_acks.lock().await.insert(source.source_uid.clone(), ack_sender);
+        let ack = match self.send_direct(peer, &prefixed).await {
+            Ok(()) => timeout(self.inner.config.ack_timeout, ack).await.ok().and_then(Result::ok),
+            Err(e) => {
+                println!("📭 {peer} is unreachable: {e}");
+                None
+            }
+        };
+        self.inner.pending_acks.lock().await.remove(&source.source_uid);
+
+        match ack {
+            Some(ack) if ack.success => Ok(Delivery::Acked),
+            Some(ack) => bail!("{peer} rejected {name}: {}", ack.error_message),
+            None => {
+                self.store_in_mailbox(peer, peer_keys, prefixed).await?;
+                Ok(Delivery::StoredInMailbox)
+            }
+        }
+    }
+
+    async fn send_direct(&self, peer: &NodeAddress, prefixed: &PrefixedSealedAndSignedMessage) -> Result<()> {
+        let connection = self.connection_to(peer).await?;
+        connection
+            .send(NetworkEnvelope {
+                message_version: self.inner.message_version.clone(),
+                message: Some(EnvMsg::PrefixedSealedAndSignedMessage(prefixed.clone())),
+            })
+            .await
+    }
+
+    /// Our open connection to `peer`, or a new one
+    async fn connection_to(&self, peer: &NodeAddress) -> Result<Connection> {
+        let key = peer.to_string();
+        let mut peers = self.inner.peers.lock().await;
+        if let Some(connection) = peers.get(&key).filter(|connection| !connection.is_closed()) {
+            return Ok(connection.clone());
+        }
+
+        let router = self.inner.router.upgrade().ok_or_else(|| anyhow!("The router has shut down"))?;
+        let stream = timeout(self.inner.config.connect_timeout, self.inner.transport.connect(&peer.host_name(), peer.port))
+            .await
+            .with_context(|| format!("Timed out connecting to {peer}"))??;
+        // Like accepted connections, these stay open between messages
+        let config = CodecConfig {
+            read_timeout: None,
+            ..CodecConfig::default()
+        };
+        let connection = Connection::spawn(stream, None, config, router);
+        self.inner
+            .connection_manager
+            .add(connection.clone(), PeerType::DirectMessage, ConnectionDirection::Outbound)
+            .await;
+        peers.insert(key, connection.clone());
+        Ok(connection)
+    }
+
+    async fn store_in_mailbox(&self, peer: &NodeAddress, peer_keys: &PubKeyRing, prefixed: PrefixedSealedAndSignedMessage) -> Result<()> {
+        let add_data = build_mailbox_add_data_message(prefixed, &peer_keys.signature_pub_key_bytes, &self.inner.key_ring)?;
+        let envelope = NetworkEnvelope {
+            message_version: self.inner.message_version.clone(),
+            message: Some(EnvMsg::AddDataMessage(add_data)),
+        };
+        match self.inner.connection_manager.broadcast(&envelope).await {
+            0 => bail!("{peer} is offline and there is no peer to leave a mailbox message with"),
+            sent => {
+                println!("📬 Left a mailbox message for {peer} with {sent} peers");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<AckMessage> for DirectMessenger {
+    async fn handle(&self, _ctx: PeerContext, ack: AckMessage) -> Result<()> {
+        if let Some(waiting) = self.inner.pending_acks.lock().await.remove(&ack.source_uid) {
+            // The sender may have given up already
+            let _ = waiting.send(ack);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<PrefixedSealedAndSignedMessage> for DirectMessenger {
+    async fn handle(&self, ctx: PeerContext, message: PrefixedSealedAndSignedMessage) -> Result<()> {
+        let sealed = message.sealed_and_signed.ok_or_else(|| anyhow!("Sealed message {} is empty", message.uid))?;
+        let unsealed = unseal(&sealed, &self.inner.key_ring).with_context(|| format!("Failed to open sealed message {}", message.uid))?;
+        if matches!(unsealed.envelope.message, Some(EnvMsg::PrefixedSealedAndSignedMessage(_))) {
+            bail!("Sealed message {} holds another sealed message", message.uid);
+        }
+
+        let router = self.inner.router.upgrade().ok_or_else(|| anyhow!("The router has shut down"))?;
+        router.dispatch(ctx.connection, unsealed.envelope).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::{storage_entry_wrapper::Message as WrapperMessage, ChatMessage};
+    use crate::p2p::middleware::AutoAck;
+    use crate::p2p::protected_entry::validate_mailbox_entry;
+    use crate::p2p::transport::MemoryTransport;
+    use crate::utils::network::codec::EnvelopeStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone, Default)]
+    struct ChatInbox(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl P2PMessageHandler<ChatMessage> for ChatInbox {
+        async fn handle(&self, _ctx: PeerContext, _message: ChatMessage) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct Node {
+        messenger: DirectMessenger,
+        connection_manager: Arc<ConnectionManager>,
+        router: Arc<P2PMessageRouter>,
+        key_ring: Arc<KeyRing>,
+        address: NodeAddress,
+    }
+
+    async fn node(transport: &MemoryTransport, address: &str) -> Node {
+        let address: NodeAddress = address.parse().unwrap();
+        let router = Arc::new(P2PMessageRouter::new());
+        router.add_middleware(AutoAck::new((&address).into())).await;
+        let connection_manager = Arc::new(ConnectionManager::default());
+        let key_ring = Arc::new(KeyRing::generate().unwrap());
+        let messenger = DirectMessenger::new(
+            DirectMessageConfig {
+                connect_timeout: Duration::from_secs(5),
+                ack_timeout: Duration::from_secs(5),
+            },
+            Arc::new(transport.clone()),
+            connection_manager.clone(),
+            &router,
+            key_ring.clone(),
+            address.clone(),
+            "0X",
+        );
+        messenger.register(&router).await;
+        Node { messenger, connection_manager, router, key_ring, address }
+    }
+
+    /// Accept connections to `node` on the in-memory network
+    fn listen(transport: &MemoryTransport, node: &Node) {
+        let mut listener = transport.listen(&node.address.host_name(), node.address.port);
+        let router = node.router.clone();
+        tokio::spawn(async move {
+            while let Some(stream) = listener.accept().await {
+                Connection::spawn(stream, None, CodecConfig::default(), router.clone());
+            }
+        });
+    }
+
+    fn chat(uid: &str) -> ChatMessage {
+        ChatMessage {
+            trade_id: "trade-1".into(),
+            uid: uid.into(),
+            message: "hello".into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_to_online_peers() {
+        let transport = MemoryTransport::new();
+        let alice = node(&transport, "alice.test:9999").await;
+        let bob = node(&transport, "bob.test:9999").await;
+        let inbox = ChatInbox::default();
+        bob.router.register(inbox.clone()).await;
+        listen(&transport, &bob);
+
+        let bob_keys = bob.key_ring.pub_key_ring().unwrap();
+        let delivery = alice.messenger.send(&bob.address, &bob_keys, chat("uid-1")).await.unwrap();
+        assert_eq!(delivery, Delivery::Acked);
+        assert_eq!(inbox.0.load(Ordering::SeqCst), 1);
+
+        // The connection is reused for the next message
+        alice.messenger.send(&bob.address, &bob_keys, chat("uid-2")).await.unwrap();
+        assert_eq!(alice.connection_manager.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn leaves_mailbox_messages_for_offline_peers() {
+        let transport = MemoryTransport::new();
+        let alice = node(&transport, "alice.test:9999").await;
+        let bob = node(&transport, "bob.test:9999").await;
+
+        // A seed to leave the message with
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        let seed = Connection::spawn(local, None, CodecConfig::default(), alice.router.clone());
+        alice.connection_manager.add(seed, PeerType::Seed, ConnectionDirection::Outbound).await;
+
+        let bob_keys = bob.key_ring.pub_key_ring().unwrap();
+        let delivery = alice.messenger.send(&bob.address, &bob_keys, chat("uid-1")).await.unwrap();
+        assert_eq!(delivery, Delivery::StoredInMailbox);
+
+        let envelope = EnvelopeStream::new(remote).read_envelope().await.unwrap().unwrap();
+        let Some(EnvMsg::AddDataMessage(add_data)) = envelope.message else {
+            panic!("expected an AddDataMessage, got {envelope:?}");
+        };
+        let Some(WrapperMessage::ProtectedMailboxStorageEntry(entry)) = add_data.entry.unwrap().message else {
+            panic!("expected a mailbox entry");
+        };
+        validate_mailbox_entry(&entry, chrono::Utc::now().timestamp_millis()).unwrap();
+        assert_eq!(entry.receivers_pub_key_bytes, bob_keys.signature_pub_key_bytes);
+    }
+
+    #[tokio::test]
+    async fn fails_without_peer_or_mailbox() {
+        let transport = MemoryTransport::new();
+        let alice = node(&transport, "alice.test:9999").await;
+        let bob = node(&transport, "bob.test:9999").await;
+
+        let bob_keys = bob.key_ring.pub_key_ring().unwrap();
+        assert!(alice.messenger.send(&bob.address, &bob_keys, chat("uid-1")).await.is_err());
+    }
+
+    #[test]
+    fn prefix_hash_covers_the_first_two_characters() {
+        let a: NodeAddress = "abcdef.test:9999".parse().unwrap();
+        let b: NodeAddress = "abzzzz.test:1".parse().unwrap();
+        let c: NodeAddress = "zz.test:9999".parse().unwrap();
+        assert_eq!(address_prefix_hash(&a), address_prefix_hash(&b));
+        assert_ne!(address_prefix_hash(&a), address_prefix_hash(&c));
+    }
+}
@@ -1,24 +1,38 @@
 use async_trait::async_trait;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use std::sync::Arc;
 use crate::p2p::router::P2PMessageHandler;
 use crate::p2p::context::PeerContext;
-use crate::generated::io_haveno_protobuffer::network_envelope::Message as EnvMsg;
+use crate::p2p::data_storage::P2PDataStorage;
+use crate::generated::io_haveno_protobuffer::AddDataMessage;
 
 
-pub struct AddDataMessageHandler;
+/// Adds the entry of an `AddDataMessage` to the data storage; an entry that isn't validly
+/// signed by its owner or is older than the version we have is refused with the reason.
+#[derive(Clone)]
+pub struct AddDataMessageHandler {
+    storage: Arc<P2PDataStorage>,
+}
 
-#[async_trait]
-impl P2PMessageHandler for AddDataMessageHandler {
-    async fn handle(&self,ctx: PeerContext) -> Result<()> {
-        if let Some(EnvMsg::AddDataMessage(msg)) = ctx.envelope.message {
-            println!("📥 [AddDataMessageHandler] Received AddDataMessage: {:#?}", msg);
+impl AddDataMessageHandler {
+    pub fn new(storage: Arc<P2PDataStorage>) -> Self {
+        Self { storage }
+    }
+}
 
-            // 🔁 Optional: I don't think this message needs a response just remove from persisted storage
+#[async_trait]
+impl P2PMessageHandler<AddDataMessage> for AddDataMessageHandler {
+    async fn handle(&self, ctx: PeerContext, msg: AddDataMessage) -> Result<()> {
+        let Some(entry) = msg.entry.and_then(|wrapper| wrapper.message) else {
+            bail!("AddDataMessage without an entry");
+        };
 
-        } else {
-            println!("⚠️ [AddDataMessageHandler] Unexpected message type.");
+        let now = chrono::Utc::now().timestamp_millis();
+        match self.storage.add(entry, now).await {
+            Ok(true) => println!("📥 [AddDataMessageHandler] Stored entry from connection {}", ctx.connection.id()),
+            Ok(false) => {}
+            Err(e) => bail!("Rejected AddDataMessage from connection {}: {e}", ctx.connection.id()),
         }
-
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use crate::p2p::router::P2PMessageHandler;
+use crate::p2p::context::PeerContext;
+use crate::p2p::append_only_store::AppendOnlyDataStore;
+use crate::generated::io_haveno_protobuffer::AddPersistableNetworkPayloadMessage;
+
+/// Adds the account age witness, signed witness or trade statistics of an
+/// `AddPersistableNetworkPayloadMessage` to the append-only store
+#[derive(Clone)]
+pub struct AddPersistableNetworkPayloadMessageHandler {
+    store: Arc<AppendOnlyDataStore>,
+}
+
+impl AddPersistableNetworkPayloadMessageHandler {
+    pub fn new(store: Arc<AppendOnlyDataStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<AddPersistableNetworkPayloadMessage> for AddPersistableNetworkPayloadMessageHandler {
+    async fn handle(&self, ctx: PeerContext, msg: AddPersistableNetworkPayloadMessage) -> Result<()> {
+        let Some(payload) = msg.payload else {
+            bail!("AddPersistableNetworkPayloadMessage without a payload");
+        };
+
+        match self.store.add(payload).await {
+            Ok(true) => println!("📥 [AddPersistableNetworkPayloadMessageHandler] Stored payload from connection {}", ctx.connection.id()),
+            Ok(false) => {}
+            Err(e) => bail!("Rejected AddPersistableNetworkPayloadMessage from connection {}: {e}", ctx.connection.id()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::p2p::router::{P2PMessageHandler, P2PMessageRouter};
+use crate::p2p::context::PeerContext;
+use crate::p2p::append_only_store::AppendOnlyDataStore;
+use crate::p2p::capability;
+use crate::p2p::data_storage::P2PDataStorage;
+use crate::generated::io_haveno_protobuffer::{
+    storage_entry_wrapper::Message as WrapperMessage, GetDataResponse, GetUpdatedDataRequest,
+    PreliminaryGetDataRequest, StorageEntryWrapper,
+};
+
+/// Most protected entries, and separately most network payloads, one response carries,
+/// Haveno's `GetDataRequestHandler.MAX_ENTRIES`. The rest follow once the peer asks again
+/// with what it got excluded.
+pub const MAX_ENTRIES: usize = 5000;
+
+/// Answers `PreliminaryGetDataRequest` and `GetUpdatedDataRequest` from our stores, which is
+/// what a seed node does for every peer that starts up
+#[derive(Clone)]
+pub struct GetDataRequestHandler {
+    storage: Arc<P2PDataStorage>,
+    store: Arc<AppendOnlyDataStore>,
+}
+
+impl GetDataRequestHandler {
+    pub fn new(storage: Arc<P2PDataStorage>, store: Arc<AppendOnlyDataStore>) -> Self {
+        Self { storage, store }
+    }
+
+    pub async fn register(&self, router: &P2PMessageRouter) {
+        router.register::<PreliminaryGetDataRequest, _>(self.clone()).await;
+        router.register::<GetUpdatedDataRequest, _>(self.clone()).await;
+    }
+
+    /// Everything we have that the peer doesn't: entries whose key isn't in `excluded_keys`,
+    /// leaving out payload types the peer lacks the capability for
+    pub async fn build_response(
+        &self,
+        nonce: i32,
+        excluded_keys: &[Vec<u8>],
+        peer_capabilities: &[i32],
+        is_get_updated_data_response: bool,
+    ) -> GetDataResponse {
+        let excluded: HashSet<&[u8]> = excluded_keys.iter().map(Vec::as_slice).collect();
+        let supported = |required: Option<i32>| required.is_none_or(|c| peer_capabilities.contains(&c));
+
+        let mut data_set: Vec<StorageEntryWrapper> = self
+            .storage
+            .entries()
+            .await
+            .into_iter()
+            .filter(|(hash, _)| !excluded.contains(hash.as_slice()))
+            .filter(|(_, entry)| supported(entry_capability(entry)))
+            .map(|(_, entry)| StorageEntryWrapper { message: Some(entry) })
+            .collect();
+
+        let mut payloads: Vec<_> = self
+            .store
+            .payloads()
+            .await
+            .into_iter()
+            .filter(|(hash, _)| !excluded.contains(hash.as_slice()))
+            .filter(|(_, payload)| {
+                supported(payload.message.as_ref().and_then(capability::required_for_network_payload))
+            })
+            .map(|(_, payload)| payload)
+            .collect();
+
+        let was_truncated = data_set.len() > MAX_ENTRIES || payloads.len() > MAX_ENTRIES;
+        data_set.truncate(MAX_ENTRIES);
+        payloads.truncate(MAX_ENTRIES);
+
+        GetDataResponse {
+            request_nonce: nonce,
+            is_get_updated_data_response,
+            data_set,
+            supported_capabilities: capability::SUPPORTED.to_vec(),
+            persistable_network_payload_items: payloads,
+            was_truncated,
+        }
+    }
+}
+
+fn entry_capability(entry: &WrapperMessage) -> Option<i32> {
+    let WrapperMessage::ProtectedStorageEntry(entry) = entry else {
+        return None;
+    };
+    entry
+        .storage_payload
+        .as_ref()
+        .and_then(|payload| payload.message.as_ref())
+        .and_then(capability::required_for_storage_payload)
+}
+
+#[async_trait]
+impl P2PMessageHandler<PreliminaryGetDataRequest> for GetDataRequestHandler {
+    async fn handle(&self, ctx: PeerContext, msg: PreliminaryGetDataRequest) -> Result<()> {
+        let response = self
+            .build_response(msg.nonce, &msg.excluded_keys, &msg.supported_capabilities, false)
+            .await;
+        println!(
+            "📤 [GetDataRequestHandler] Answering connection {} with {} entries and {} payloads",
+            ctx.connection.id(),
+            response.data_set.len(),
+            response.persistable_network_payload_items.len()
+        );
+        ctx.reply(response).await
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<GetUpdatedDataRequest> for GetDataRequestHandler {
+    async fn handle(&self, ctx: PeerContext, msg: GetUpdatedDataRequest) -> Result<()> {
+        // Only the preliminary request carries capabilities, the connection remembers them
+        let capabilities = ctx.connection.state().await.capabilities;
+        let response = self.build_response(msg.nonce, &msg.excluded_keys, &capabilities, true).await;
+        println!(
+            "📤 [GetDataRequestHandler] Answering update from connection {} with {} entries and {} payloads",
+            ctx.connection.id(),
+            response.data_set.len(),
+            response.persistable_network_payload_items.len()
+        );
+        ctx.reply(response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::{add_data, filter};
+    use crate::crypto::key_ring::KeyRing;
+    use crate::generated::io_haveno_protobuffer::{
+        persistable_network_payload::Message as NetworkPayloadMessage, storage_payload::Message as PayloadMessage,
+        AccountAgeWitness, NetworkEnvelope, PersistableNetworkPayload, TradeStatistics3,
+    };
+    use crate::p2p::connection::Connection;
+    use crate::p2p::protected_entry::payload_hash;
+    use crate::utils::network::codec::{CodecConfig, EnvelopeStream};
+    use crate::utils::network::envelope::EnvMsg;
+
+    fn payload(message: NetworkPayloadMessage) -> PersistableNetworkPayload {
+        PersistableNetworkPayload { message: Some(message) }
+    }
+
+    async fn handler() -> GetDataRequestHandler {
+        let storage = Arc::new(P2PDataStorage::new());
+        let key_ring = KeyRing::generate().unwrap();
+        let filter = filter::build_signed_filter(&key_ring).unwrap();
+        let message = add_data::build_add_data_message(PayloadMessage::Filter(filter), &key_ring).unwrap();
+        let now = chrono::Utc::now().timestamp_millis();
+        storage.add(message.entry.unwrap().message.unwrap(), now).await.unwrap();
+
+        let store = Arc::new(AppendOnlyDataStore::new());
+        for seed in 1..=2 {
+            let witness = AccountAgeWitness { hash: vec![seed; 20], date: 1 };
+            store.add(payload(NetworkPayloadMessage::AccountAgeWitness(witness))).await.unwrap();
+        }
+        let statistics = TradeStatistics3 { hash: vec![3; 20], ..Default::default() };
+        store.add(payload(NetworkPayloadMessage::TradeStatistics3(statistics))).await.unwrap();
+
+        GetDataRequestHandler::new(storage, store)
+    }
+
+    #[tokio::test]
+    async fn leaves_out_excluded_keys_and_unsupported_payloads() {
+        let handler = handler().await;
+
+        let everything = handler.build_response(7, &[], &capability::SUPPORTED, false).await;
+        assert_eq!(everything.request_nonce, 7);
+        assert_eq!(everything.data_set.len(), 1);
+        assert_eq!(everything.persistable_network_payload_items.len(), 3);
+        assert!(!everything.was_truncated);
+
+        let Some(WrapperMessage::ProtectedStorageEntry(entry)) = &everything.data_set[0].message else {
+            panic!("expected a protected entry");
+        };
+        let known = vec![payload_hash(entry.storage_payload.as_ref().unwrap()).to_vec(), vec![1; 20]];
+        let rest = handler.build_response(8, &known, &capability::SUPPORTED, true).await;
+        assert!(rest.data_set.is_empty());
+        assert_eq!(rest.persistable_network_payload_items.len(), 2);
+        assert!(rest.is_get_updated_data_response);
+
+        // Without TRADE_STATISTICS_3 the trade statistics stay home
+        let old_peer = handler.build_response(9, &known, &[capability::SIGNED_ACCOUNT_AGE_WITNESS], true).await;
+        assert_eq!(old_peer.persistable_network_payload_items.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn answers_requests_on_the_connection() {
+        let router = Arc::new(P2PMessageRouter::new());
+        handler().await.register(&router).await;
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        let _connection = Connection::spawn(local, None, CodecConfig::default(), router);
+        let mut peer = EnvelopeStream::new(remote);
+
+        let request = PreliminaryGetDataRequest {
+            nonce: 42,
+            excluded_keys: vec![],
+            supported_capabilities: capability::SUPPORTED.to_vec(),
+            version: "1.1.2".into(),
+        };
+        let envelope = NetworkEnvelope {
+            message_version: "0X".into(),
+            message: Some(EnvMsg::PreliminaryGetDataRequest(request)),
+        };
+        peer.write_envelope(&envelope).await.unwrap();
+
+        let Some(EnvMsg::GetDataResponse(response)) = peer.read_envelope().await.unwrap().unwrap().message else {
+            panic!("expected a GetDataResponse");
+        };
+        assert_eq!(response.request_nonce, 42);
+        assert_eq!(response.data_set.len(), 1);
+        assert_eq!(response.persistable_network_payload_items.len(), 3);
+    }
+}
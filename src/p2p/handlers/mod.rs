@@ -1 +1,5 @@
-pub mod add_data;
\ No newline at end of file
+pub mod add_data;
+pub mod remove_data;
+pub mod refresh_offer;
+pub mod add_persistable_network_payload;
+pub mod get_data;
@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use crate::p2p::router::P2PMessageHandler;
+use crate::p2p::context::PeerContext;
+use crate::p2p::data_storage::P2PDataStorage;
+use crate::p2p::protected_entry::EntryError;
+use crate::generated::io_haveno_protobuffer::RefreshOfferMessage;
+
+/// Extends the life of a stored offer for a `RefreshOfferMessage` signed by its owner
+#[derive(Clone)]
+pub struct RefreshOfferMessageHandler {
+    storage: Arc<P2PDataStorage>,
+}
+
+impl RefreshOfferMessageHandler {
+    pub fn new(storage: Arc<P2PDataStorage>) -> Self {
+        Self { storage }
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<RefreshOfferMessage> for RefreshOfferMessageHandler {
+    async fn handle(&self, ctx: PeerContext, msg: RefreshOfferMessage) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        match self.storage.refresh(msg, now).await {
+            // Refreshes for offers we never received are relayed to us all the time
+            Ok(_) | Err(EntryError::UnknownEntry) => Ok(()),
+            Err(e) => bail!("Rejected RefreshOfferMessage from connection {}: {e}", ctx.connection.id()),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use crate::p2p::router::{P2PMessageHandler, P2PMessageRouter};
+use crate::p2p::context::PeerContext;
+use crate::p2p::data_storage::P2PDataStorage;
+use crate::generated::io_haveno_protobuffer::{RemoveDataMessage, RemoveMailboxDataMessage};
+
+/// Removes entries from the data storage for `RemoveDataMessage` and `RemoveMailboxDataMessage`,
+/// when signed by whoever may remove them
+#[derive(Clone)]
+pub struct RemoveDataMessageHandler {
+    storage: Arc<P2PDataStorage>,
+}
+
+impl RemoveDataMessageHandler {
+    pub fn new(storage: Arc<P2PDataStorage>) -> Self {
+        Self { storage }
+    }
+
+    pub async fn register(&self, router: &P2PMessageRouter) {
+        router.register::<RemoveDataMessage, _>(self.clone()).await;
+        router.register::<RemoveMailboxDataMessage, _>(self.clone()).await;
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<RemoveDataMessage> for RemoveDataMessageHandler {
+    async fn handle(&self, ctx: PeerContext, msg: RemoveDataMessage) -> Result<()> {
+        let Some(entry) = msg.protected_storage_entry else {
+            bail!("RemoveDataMessage without an entry");
+        };
+
+        let now = chrono::Utc::now().timestamp_millis();
+        match self.storage.remove(entry, now).await {
+            Ok(true) => println!("🗑️ [RemoveDataMessageHandler] Removed entry for connection {}", ctx.connection.id()),
+            Ok(false) => {}
+            Err(e) => bail!("Rejected RemoveDataMessage from connection {}: {e}", ctx.connection.id()),
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<RemoveMailboxDataMessage> for RemoveDataMessageHandler {
+    async fn handle(&self, ctx: PeerContext, msg: RemoveMailboxDataMessage) -> Result<()> {
+        let Some(entry) = msg.protected_storage_entry else {
+            bail!("RemoveMailboxDataMessage without an entry");
+        };
+
+        let now = chrono::Utc::now().timestamp_millis();
+        match self.storage.remove_mailbox(entry, now).await {
+            Ok(true) => println!("🗑️ [RemoveDataMessageHandler] Removed mailbox entry for connection {}", ctx.connection.id()),
+            Ok(false) => {}
+            Err(e) => bail!("Rejected RemoveMailboxDataMessage from connection {}: {e}", ctx.connection.id()),
+        }
+        Ok(())
+    }
+}
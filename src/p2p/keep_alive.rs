@@ -0,0 +1,239 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use crate::generated::io_haveno_protobuffer::{NetworkEnvelope, Ping, Pong};
+use crate::p2p::connection::Connection;
+use crate::p2p::context::PeerContext;
+use crate::p2p::router::{P2PMessageHandler, P2PMessageRouter};
+use crate::utils::network::message::EnvelopeMessage;
+
+/// `CloseConnectionReason.SOCKET_TIMEOUT` in Haveno
+const TIMEOUT_REASON: &str = "SOCKET_TIMEOUT";
+
+#[derive(Debug, Clone)]
+pub struct KeepAliveConfig {
+    /// How often every tracked connection is pinged
+    pub interval: Duration,
+    /// How long a peer has to answer a ping before its connection is closed
+    pub pong_timeout: Duration,
+}
+
+impl Default for KeepAliveConfig {
+    /// Haveno's `KeepAliveManager` pings every 30 seconds
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            pong_timeout: Duration::from_secs(90),
+        }
+    }
+}
+
+struct TrackedConnection {
+    connection: Connection,
+    /// Nonce and send time of the ping we are waiting on
+    pending: Option<(i32, Instant)>,
+}
+
+struct KeepAliveInner {
+    config: KeepAliveConfig,
+    connections: Mutex<HashMap<u64, TrackedConnection>>,
+}
+
+/// Keeps connections alive with `Ping`/`Pong`, measures round-trip times and closes
+/// connections whose peer stopped answering.
+///
+/// Register it with the router to answer pings and receive pongs, `track` the connections
+/// to watch, and `spawn` the ping loop.
+#[derive(Clone)]
+pub struct KeepAlive {
+    inner: Arc<KeepAliveInner>,
+}
+
+impl KeepAlive {
+    pub fn new(config: KeepAliveConfig) -> Self {
+        Self {
+            inner: Arc::new(KeepAliveInner {
+                config,
+                connections: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Route incoming `Ping` and `Pong` messages to this service
+    pub async fn register(&self, router: &P2PMessageRouter) {
+        router.register::<Ping, _>(self.clone()).await;
+        router.register::<Pong, _>(self.clone()).await;
+    }
+
+    pub async fn track(&self, connection: Connection) {
+        self.inner.connections.lock().await.entry(connection.id()).or_insert(TrackedConnection {
+            connection,
+            pending: None,
+        });
+    }
+
+    /// Run the ping loop until `shutdown` is cancelled
+    pub fn spawn(&self, shutdown: CancellationToken) -> tokio::task::JoinHandle<()> {
+        let keep_alive = self.clone();
+        tokio::spawn(async move {
+            let mut ticks = interval(keep_alive.inner.config.interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = ticks.tick() => keep_alive.check_connections().await,
+                }
+            }
+        })
+    }
+
+    /// Close connections with an overdue pong and ping the rest
+    async fn check_connections(&self) {
+        let mut connections = self.inner.connections.lock().await;
+        connections.retain(|_, tracked| !tracked.connection.is_closed());
+
+        let mut timed_out = Vec::new();
+        for (id, tracked) in connections.iter_mut() {
+            match tracked.pending {
+                Some((_, sent_at)) if sent_at.elapsed() >= self.inner.config.pong_timeout => {
+                    timed_out.push(*id);
+                }
+                Some(_) => {}
+                None => {
+                    let nonce = rand::random::<i32>();
+                    let last_round_trip_time = tracked.connection.state().await.round_trip_time;
+                    let ping = Ping {
+                        nonce,
+                        last_round_trip_time: last_round_trip_time
+                            .map_or(0, |rtt| rtt.as_millis().min(i32::MAX as u128) as i32),
+                    };
+                    tracked.pending = Some((nonce, Instant::now()));
+                    if let Err(e) = send(&tracked.connection, ping).await {
+                        eprintln!("❌ Failed to ping connection {id}: {e}");
+                    }
+                }
+            }
+        }
+
+        for id in timed_out {
+            if let Some(tracked) = connections.remove(&id) {
+                println!("⏱️ Connection {id} stopped answering pings, closing it");
+                tracked.connection.close_with_reason(TIMEOUT_REASON).await;
+            }
+        }
+    }
+}
+
+/// Send a message outside of a reply, in the version the peer speaks
+async fn send<M: EnvelopeMessage>(connection: &Connection, message: M) -> Result<()> {
+    let envelope = NetworkEnvelope {
+        message_version: connection.state().await.message_version,
+        message: Some(message.into_envelope_message()),
+    };
+    connection.send(envelope).await
+}
+
+#[async_trait]
+impl P2PMessageHandler<Ping> for KeepAlive {
+    async fn handle(&self, ctx: PeerContext, ping: Ping) -> Result<()> {
+        ctx.reply(Pong { request_nonce: ping.nonce }).await
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<Pong> for KeepAlive {
+    async fn handle(&self, ctx: PeerContext, pong: Pong) -> Result<()> {
+        let mut connections = self.inner.connections.lock().await;
+        let Some(tracked) = connections.get_mut(&ctx.connection.id()) else {
+            return Ok(());
+        };
+
+        match tracked.pending {
+            Some((nonce, sent_at)) if nonce == pong.request_nonce => {
+                tracked.pending = None;
+                let round_trip_time = sent_at.elapsed();
+                ctx.connection.update_state(|state| state.round_trip_time = Some(round_trip_time)).await;
+            }
+            _ => println!("⚠️ Unexpected pong nonce {} on connection {}", pong.request_nonce, ctx.connection.id()),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::network::codec::{CodecConfig, EnvelopeStream};
+    use crate::utils::network::envelope::EnvMsg;
+    use tokio::io::DuplexStream;
+
+    async fn connect(keep_alive: &KeepAlive) -> (Connection, EnvelopeStream<DuplexStream>) {
+        let router = Arc::new(P2PMessageRouter::new());
+        keep_alive.register(&router).await;
+
+        let (local, remote) = tokio::io::duplex(1024);
+        let connection = Connection::spawn(local, None, CodecConfig::default(), router);
+        keep_alive.track(connection.clone()).await;
+        (connection, EnvelopeStream::new(remote))
+    }
+
+    fn envelope(message: EnvMsg) -> NetworkEnvelope {
+        NetworkEnvelope {
+            message_version: "0X".into(),
+            message: Some(message),
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_pings() {
+        let keep_alive = KeepAlive::new(KeepAliveConfig::default());
+        let (_connection, mut peer) = connect(&keep_alive).await;
+
+        peer.write_envelope(&envelope(EnvMsg::Ping(Ping { nonce: 42, last_round_trip_time: 0 }))).await.unwrap();
+        let reply = peer.read_envelope().await.unwrap();
+        assert_eq!(reply, Some(envelope(EnvMsg::Pong(Pong { request_nonce: 42 }))));
+    }
+
+    #[tokio::test]
+    async fn records_round_trip_time() {
+        let keep_alive = KeepAlive::new(KeepAliveConfig::default());
+        let (connection, mut peer) = connect(&keep_alive).await;
+
+        keep_alive.check_connections().await;
+        let Some(EnvMsg::Ping(ping)) = peer.read_envelope().await.unwrap().unwrap().message else {
+            panic!("expected a ping");
+        };
+        peer.write_envelope(&envelope(EnvMsg::Pong(Pong { request_nonce: ping.nonce }))).await.unwrap();
+
+        while connection.state().await.round_trip_time.is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        // The pong cleared the pending ping, so the next round goes out
+        keep_alive.check_connections().await;
+        let next = peer.read_envelope().await.unwrap().unwrap();
+        assert!(matches!(next.message, Some(EnvMsg::Ping(_))));
+        assert!(!connection.is_closed());
+    }
+
+    #[tokio::test]
+    async fn closes_connections_that_stop_answering() {
+        let keep_alive = KeepAlive::new(KeepAliveConfig {
+            interval: Duration::from_secs(30),
+            pong_timeout: Duration::ZERO,
+        });
+        let (connection, mut peer) = connect(&keep_alive).await;
+
+        keep_alive.check_connections().await;
+        assert!(matches!(peer.read_envelope().await.unwrap().unwrap().message, Some(EnvMsg::Ping(_))));
+
+        keep_alive.check_connections().await;
+        connection.finished().await;
+        let close = peer.read_envelope().await.unwrap().unwrap();
+        assert!(matches!(close.message, Some(EnvMsg::CloseConnectionMessage(m)) if m.reason == TIMEOUT_REASON));
+    }
+}
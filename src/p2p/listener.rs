@@ -1,55 +1,172 @@
 use anyhow::Result;
 use tokio::net::{TcpListener};
-use tokio::sync::broadcast;
+use tokio::sync::Mutex;
+use tokio::time::{timeout, Duration};
+use tokio_util::sync::CancellationToken;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use crate::p2p::context::PeerContext;
+use crate::p2p::connection::Connection;
+use crate::p2p::connection_manager::{ConnectionDirection, ConnectionManager, PeerType};
 use crate::p2p::router::P2PMessageRouter;
-use crate::utils::network::envelope::recv_envelope;
+use crate::utils::network::codec::CodecConfig;
+
+/// How long `start` waits for connections to wind down after `shutdown`
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `CloseConnectionReason.APP_SHUT_DOWN` in Haveno
+const SHUTDOWN_REASON: &str = "APP_SHUT_DOWN";
+
 pub struct P2PListener {
-    port: u16,
-    shutdown_tx: broadcast::Sender<()>,
+    addr: SocketAddr,
+    drain_timeout: Duration,
+    shutdown: CancellationToken,
+    connections: Mutex<HashMap<u64, Connection>>,
+    connection_manager: Option<Arc<ConnectionManager>>,
 }
 
 impl P2PListener {
+    /// Listen on `port` on all interfaces
     pub fn new(port: u16) -> Self {
-        let (shutdown_tx, _) = broadcast::channel(1);
-        Self { port, shutdown_tx }
+        Self::bind_to(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+    }
+
+    pub fn bind_to(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            shutdown: CancellationToken::new(),
+            connections: Mutex::new(HashMap::new()),
+            connection_manager: None,
+        }
+    }
+
+    /// Hand accepted connections to `connection_manager`, which may close them to stay within limits
+    pub fn with_connection_manager(mut self, connection_manager: Arc<ConnectionManager>) -> Self {
+        self.connection_manager = Some(connection_manager);
+        self
+    }
+
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
     }
 
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Bind the configured address and serve until `shutdown` is called
     pub async fn start(&self, router: Arc<P2PMessageRouter>) -> Result<()> {
-        let port = 3333;
-        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
-        println!("[👂] Listening on port {port}...");
+        let listener = TcpListener::bind(self.addr).await?;
+        self.serve(listener, router).await
+    }
+
+    /// Serve connections from an already bound listener until `shutdown` is called
+    pub async fn serve(&self, listener: TcpListener, router: Arc<P2PMessageRouter>) -> Result<()> {
+        println!("[👂] Listening on {}...", listener.local_addr()?);
+
+        // Peers keep their connections open between messages, so no read timeout
+        let config = CodecConfig {
+            read_timeout: None,
+            ..CodecConfig::default()
+        };
 
         loop {
-            let (socket, _) = listener.accept().await?;
-            let router = router.clone();
-
-            tokio::spawn(async move {
-                let mut socket = socket; // Make it mutable in this scope
-
-                match recv_envelope(&mut socket).await {
-                    Ok(Some(envelope)) => {
-                        let ctx = PeerContext {
-                            stream: socket, // You still own the full socket here
-                            envelope,
-                        };
-                        if let Err(err) = router.dispatch(ctx).await {
-                            eprintln!("❌ Router error: {err}");
-                        }
-                    }
-                    Ok(None) => {
-                        eprintln!("⚠️ Received empty envelope");
-                    }
-                    Err(e) => {
-                        eprintln!("❌ Failed to decode envelope: {e}");
-                    }
-                }
-            });
+            let (socket, addr) = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                accepted = listener.accept() => accepted?,
+            };
+
+            let connection = Connection::spawn(socket, Some(addr), config.clone(), router.clone());
+            println!("[+] Connection {} from {addr}", connection.id());
+
+            let mut connections = self.connections.lock().await;
+            connections.retain(|_, c| !c.is_closed());
+            connections.insert(connection.id(), connection.clone());
+            drop(connections);
+
+            if let Some(manager) = &self.connection_manager {
+                manager.add(connection, PeerType::Peer, ConnectionDirection::Inbound).await;
+            }
         }
+
+        drop(listener);
+        self.drain().await;
+        Ok(())
     }
 
+    /// Say goodbye to every open connection and wait for their handlers, up to the drain timeout
+    async fn drain(&self) {
+        let connections: Vec<Connection> = self.connections.lock().await.drain().map(|(_, c)| c).collect();
+        println!("[👋] Closing {} connection(s)...", connections.len());
+
+        let close_all = async {
+            for connection in &connections {
+                connection.close_with_reason(SHUTDOWN_REASON).await;
+            }
+            for connection in &connections {
+                connection.finished().await;
+            }
+        };
+
+        if timeout(self.drain_timeout, close_all).await.is_err() {
+            eprintln!("⚠️ Connections still busy after {:?}, giving up on them", self.drain_timeout);
+        }
+    }
+
+    /// Stop accepting connections. `start` returns once open connections have been drained.
     pub fn shutdown(&self) {
-        let _ = self.shutdown_tx.send(());
+        self.shutdown.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::{CloseConnectionMessage, NetworkEnvelope, Ping};
+    use crate::utils::network::codec::EnvelopeStream;
+    use crate::utils::network::envelope::EnvMsg;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn shutdown_closes_peers_and_returns() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let p2p = Arc::new(P2PListener::bind_to(addr));
+        let serve = tokio::spawn({
+            let p2p = p2p.clone();
+            async move { p2p.serve(listener, Arc::new(P2PMessageRouter::new())).await }
+        });
+
+        let mut peer = EnvelopeStream::new(TcpStream::connect(addr).await.unwrap());
+        let ping = NetworkEnvelope {
+            message_version: "0X".into(),
+            message: Some(EnvMsg::Ping(Ping { nonce: 1, last_round_trip_time: 0 })),
+        };
+        peer.write_envelope(&ping).await.unwrap();
+
+        // Wait until the listener has registered the connection and read the ping
+        loop {
+            let connection = p2p.connections.lock().await.values().next().cloned();
+            if let Some(connection) = connection {
+                if connection.state().await.message_version == "0X" {
+                    break;
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+        p2p.shutdown();
+        serve.await.unwrap().unwrap();
+
+        let close = peer.read_envelope().await.unwrap().unwrap();
+        assert_eq!(close.message_version, "0X");
+        assert_eq!(
+            close.message,
+            Some(EnvMsg::CloseConnectionMessage(CloseConnectionMessage { reason: SHUTDOWN_REASON.into() }))
+        );
+        assert_eq!(peer.read_envelope().await.unwrap(), None);
+        assert!(TcpStream::connect(addr).await.is_err());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use anyhow::Result;
+use std::collections::HashSet;
+use tokio::sync::RwLock;
+use crate::generated::io_haveno_protobuffer::{Filter, NodeAddress};
+use crate::p2p::context::PeerContext;
+use crate::utils::network::ack::build_ack;
+use crate::utils::network::envelope::EnvMsg;
+
+/// What the router should do with a message after a middleware has looked at it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MiddlewareAction {
+    /// Hand the message to the next middleware, and finally the handler
+    Continue,
+    /// Drop the message but keep the connection
+    Reject(String),
+    /// Drop the message and close the connection, sending `reason` as the
+    /// `CloseConnectionMessage` reason (one of Haveno's `CloseConnectionReason` names)
+    Close(String),
+}
+
+/// Cross-cutting logic run around every handler.
+///
+/// `before` hooks run in registration order and may short-circuit the chain, `after` hooks
+/// run in reverse order once the handler has returned.
+#[async_trait]
+pub trait P2PMiddleware: Send + Sync {
+    async fn before(&self, _ctx: &mut PeerContext, _message: &EnvMsg) -> MiddlewareAction {
+        MiddlewareAction::Continue
+    }
+
+    async fn after(&self, _ctx: &PeerContext, _message_name: &'static str, _result: &Result<()>) {}
+}
+
+/// Closes connections from peers speaking another network's `message_version`
+pub struct MessageVersionCheck {
+    expected: String,
+}
+
+impl MessageVersionCheck {
+    pub fn new(expected: impl Into<String>) -> Self {
+        Self { expected: expected.into() }
+    }
+}
+
+#[async_trait]
+impl P2PMiddleware for MessageVersionCheck {
+    async fn before(&self, ctx: &mut PeerContext, _message: &EnvMsg) -> MiddlewareAction {
+        if ctx.message_version == self.expected {
+            MiddlewareAction::Continue
+        } else {
+            // What Haveno's Connection does on a version mismatch
+            MiddlewareAction::Close("RULE_VIOLATION".into())
+        }
+    }
+}
+
+/// Closes connections from peers listed in the filter's `node_addresses_banned_from_network`
+#[derive(Default)]
+pub struct BanList {
+    banned: RwLock<HashSet<String>>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the ban list with the one from a newly received filter
+    pub async fn apply_filter(&self, filter: &Filter) {
+        let banned = filter.node_addresses_banned_from_network.iter().cloned().collect();
+        *self.banned.write().await = banned;
+    }
+
+    /// `address` is a full `host:port` node address
+    pub async fn is_banned(&self, address: &str) -> bool {
+        self.banned.read().await.contains(address)
+    }
+}
+
+#[async_trait]
+impl P2PMiddleware for BanList {
+    async fn before(&self, ctx: &mut PeerContext, _message: &EnvMsg) -> MiddlewareAction {
+        let Some(address) = ctx.connection.state().await.peer_node_address else {
+            return MiddlewareAction::Continue;
+        };
+
+        if self.is_banned(&format!("{}:{}", address.host_name, address.port)).await {
+            MiddlewareAction::Close("PEER_BANNED".into())
+        } else {
+            MiddlewareAction::Continue
+        }
+    }
+}
+
+/// Answers every message Haveno expects to be acknowledged with an `AckMessage`
+/// carrying the handler's outcome
+pub struct AutoAck {
+    node_address: NodeAddress,
+}
+
+impl AutoAck {
+    /// `node_address` is our own address, reported as the ack's sender
+    pub fn new(node_address: NodeAddress) -> Self {
+        Self { node_address }
+    }
+}
+
+#[async_trait]
+impl P2PMiddleware for AutoAck {
+    async fn after(&self, ctx: &PeerContext, message_name: &'static str, result: &Result<()>) {
+        let Some(source) = &ctx.ack_source else {
+            return;
+        };
+
+        let ack = build_ack(source, self.node_address.clone(), result);
+        if let Err(e) = ctx.reply(ack).await {
+            eprintln!("❌ Failed to ack {message_name} on connection {}: {e}", ctx.connection.id());
+        }
+    }
+}
@@ -1,26 +1,48 @@
-mod listener;
+pub mod listener;
 pub mod router;
 pub mod handlers;
+pub mod address;
 pub mod onion_identity;
-mod context;
+pub mod onion_auth;
+pub mod protected_entry;
+pub mod data_storage;
+pub mod append_only_store;
+pub mod capability;
+pub mod connection;
+pub mod connection_manager;
+pub mod direct_message;
+pub mod context;
+pub mod middleware;
+pub mod keep_alive;
+pub mod transport;
+pub mod tor_control;
 
 use anyhow::Result;
 use std::sync::Arc;
 use crate::p2p::router::P2PMessageRouter;
 use crate::p2p::listener::P2PListener;
-use crate::p2p::handlers::add_data;
+use crate::p2p::append_only_store::AppendOnlyDataStore;
+use crate::p2p::data_storage::P2PDataStorage;
+use crate::p2p::handlers::{add_data, add_persistable_network_payload, get_data, refresh_offer, remove_data};
 
-/// Initialize and run the P2P layer.
-pub async fn setup() -> Result<()> {
+/// Initialize and run the P2P layer on `port`, keeping received entries in `storage` and
+/// persistable network payloads in `store`.
+/// The returned listener is what to call `shutdown` on.
+pub async fn setup(port: u16, storage: Arc<P2PDataStorage>, store: Arc<AppendOnlyDataStore>) -> Result<Arc<P2PListener>> {
     let router = Arc::new(P2PMessageRouter::new());
-    let listener = P2PListener::new(3333);
+    let listener = Arc::new(P2PListener::new(port));
 
     // ✅ Register handlers
-    router.register("AddDataMessage", add_data::AddDataMessageHandler).await;
+    router.register(add_data::AddDataMessageHandler::new(storage.clone())).await;
+    remove_data::RemoveDataMessageHandler::new(storage.clone()).register(&router).await;
+    router.register(refresh_offer::RefreshOfferMessageHandler::new(storage.clone())).await;
+    router.register(add_persistable_network_payload::AddPersistableNetworkPayloadMessageHandler::new(store.clone())).await;
+    get_data::GetDataRequestHandler::new(storage, store).register(&router).await;
 
     // 🚀 Start listener in a background task
     tokio::spawn({
         let router = router.clone();
+        let listener = listener.clone();
         async move {
             if let Err(e) = listener.start(router).await {
                 eprintln!("🔥 P2P Listener error: {e}");
@@ -28,6 +50,5 @@ pub async fn setup() -> Result<()> {
         }
     });
 
-    Ok(())
-
+    Ok(listener)
 }
\ No newline at end of file
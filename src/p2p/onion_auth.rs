@@ -0,0 +1,318 @@
+use async_trait::async_trait;
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use crate::generated::io_haveno_protobuffer::{
+    self as protobuf, NetworkEnvelope, OnionAuthChallenge, OnionAuthRequest, OnionAuthResponse,
+};
+use crate::p2p::address::{Host, NodeAddress};
+use crate::p2p::connection::Connection;
+use crate::p2p::context::PeerContext;
+use crate::p2p::middleware::{MiddlewareAction, P2PMiddleware};
+use crate::p2p::onion_identity::{verify_signature, OnionIdentity};
+use crate::p2p::router::{P2PMessageHandler, P2PMessageRouter};
+use crate::utils::network::envelope::EnvMsg;
+use crate::utils::network::message::EnvelopeMessage;
+
+/// Signed along with the nonce, so an auth signature can't be passed off as anything else
+const SIGNATURE_CONTEXT: &[u8] = b"haveno onion auth";
+
+/// `CloseConnectionReason.RULE_VIOLATION` in Haveno
+const VIOLATION_REASON: &str = "RULE_VIOLATION";
+
+fn signed_bytes(nonce: &[u8]) -> Vec<u8> {
+    [SIGNATURE_CONTEXT, nonce].concat()
+}
+
+/// The address a message claims to come from
+fn claimed_sender(message: &EnvMsg) -> Option<&protobuf::NodeAddress> {
+    match message {
+        EnvMsg::GetUpdatedDataRequest(m) => m.sender_node_address.as_ref(),
+        EnvMsg::GetPeersRequest(m) => m.sender_node_address.as_ref(),
+        EnvMsg::AckMessage(m) => m.sender_node_address.as_ref(),
+        _ => None,
+    }
+}
+
+struct OnionAuthInner {
+    required: bool,
+    /// Claimed address and nonce of the challenges we are waiting on, by connection
+    pending: Mutex<HashMap<u64, (NodeAddress, Vec<u8>)>>,
+}
+
+/// Checks that peers control the onion address they claim as `sender_node_address`.
+///
+/// A peer sends `OnionAuthRequest` with its address, we answer with a random nonce and it
+/// signs that with the address's key. Once proven, a message claiming any other sender
+/// closes the connection. Java Haveno nodes don't speak this, so unless `required`, messages
+/// from unproven peers still go through.
+#[derive(Clone)]
+pub struct OnionAuth {
+    inner: Arc<OnionAuthInner>,
+}
+
+impl OnionAuth {
+    /// Check claims from peers that authenticate, let everyone else through
+    pub fn optional() -> Self {
+        Self::new(false)
+    }
+
+    /// Reject messages with a sender address the peer hasn't proven
+    pub fn required() -> Self {
+        Self::new(true)
+    }
+
+    fn new(required: bool) -> Self {
+        Self {
+            inner: Arc::new(OnionAuthInner {
+                required,
+                pending: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Answer auth requests and check sender addresses on `router`
+    pub async fn register(&self, router: &P2PMessageRouter) {
+        router.register::<OnionAuthRequest, _>(self.clone()).await;
+        router.register::<OnionAuthResponse, _>(self.clone()).await;
+        router.add_middleware(self.clone()).await;
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<OnionAuthRequest> for OnionAuth {
+    async fn handle(&self, ctx: PeerContext, request: OnionAuthRequest) -> Result<()> {
+        let claimed = request.node_address.as_ref().ok_or_else(|| anyhow!("OnionAuthRequest without an address"))?;
+        let address = NodeAddress::try_from(claimed)?;
+        if address.onion().is_none() {
+            bail!("Only onion addresses can be authenticated, got {address}");
+        }
+
+        let nonce = rand::random::<[u8; 32]>().to_vec();
+        let earlier = self.inner.pending.lock().await.insert(ctx.connection.id(), (address, nonce.clone()));
+        if earlier.is_none() {
+            // Forget the challenge if the peer hangs up without answering it
+            let inner = self.inner.clone();
+            let connection = ctx.connection.clone();
+            tokio::spawn(async move {
+                connection.closed().await;
+                inner.pending.lock().await.remove(&connection.id());
+            });
+        }
+        ctx.reply(OnionAuthChallenge { nonce }).await
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<OnionAuthResponse> for OnionAuth {
+    async fn handle(&self, ctx: PeerContext, response: OnionAuthResponse) -> Result<()> {
+        let pending = self.inner.pending.lock().await.remove(&ctx.connection.id());
+        let Some((address, nonce)) = pending else {
+            bail!("OnionAuthResponse without a challenge");
+        };
+
+        let onion = address.onion().map(ToString::to_string).unwrap_or_default();
+        if let Err(e) = verify_signature(&onion, &signed_bytes(&nonce), &response.signature) {
+            ctx.connection.close_with_reason(VIOLATION_REASON).await;
+            bail!("Peer failed to prove it controls {address}: {e}");
+        }
+
+        println!("🔐 Connection {} authenticated as {address}", ctx.connection.id());
+        ctx.connection.update_state(|state| state.authenticated_address = Some(address)).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl P2PMiddleware for OnionAuth {
+    async fn before(&self, ctx: &mut PeerContext, message: &EnvMsg) -> MiddlewareAction {
+        let Some(claimed) = claimed_sender(message) else {
+            return MiddlewareAction::Continue;
+        };
+        let claimed = NodeAddress::try_from(claimed).ok();
+
+        match ctx.connection.state().await.authenticated_address {
+            Some(proven) if claimed.as_ref() == Some(&proven) => {
+                ctx.annotate("authenticated_address", proven.to_string());
+                MiddlewareAction::Continue
+            }
+            Some(proven) => {
+                println!("🚫 Connection {} authenticated as {proven} claimed another sender", ctx.connection.id());
+                MiddlewareAction::Close(VIOLATION_REASON.into())
+            }
+            None if self.inner.required => MiddlewareAction::Reject("sender address is not authenticated".into()),
+            None => MiddlewareAction::Continue,
+        }
+    }
+}
+
+/// Our side of the handshake: proves to peers that we control our onion address
+#[derive(Clone)]
+pub struct OnionAuthProver {
+    identity: Arc<OnionIdentity>,
+    node_address: NodeAddress,
+    message_version: String,
+}
+
+impl OnionAuthProver {
+    /// Claims `identity`'s onion address on `port`, which is where we listen
+    pub fn new(identity: Arc<OnionIdentity>, port: u16, message_version: impl Into<String>) -> Self {
+        Self {
+            node_address: NodeAddress::new(Host::Onion(identity.onion_address()), port),
+            identity,
+            message_version: message_version.into(),
+        }
+    }
+
+    /// Answer challenges on `router`
+    pub async fn register(&self, router: &P2PMessageRouter) {
+        router.register::<OnionAuthChallenge, _>(self.clone()).await;
+    }
+
+    /// Ask the peer on `connection` to challenge us
+    pub async fn start(&self, connection: &Connection) -> Result<()> {
+        let request = OnionAuthRequest {
+            node_address: Some((&self.node_address).into()),
+        };
+        connection
+            .send(NetworkEnvelope {
+                message_version: self.message_version.clone(),
+                message: Some(request.into_envelope_message()),
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl P2PMessageHandler<OnionAuthChallenge> for OnionAuthProver {
+    async fn handle(&self, ctx: PeerContext, challenge: OnionAuthChallenge) -> Result<()> {
+        let signature = self.identity.sign(&signed_bytes(&challenge.nonce));
+        ctx.reply(OnionAuthResponse {
+            signature: signature.to_bytes().to_vec(),
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::GetUpdatedDataRequest;
+    use crate::utils::network::codec::CodecConfig;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone, Default)]
+    struct CountingHandler(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl P2PMessageHandler<GetUpdatedDataRequest> for CountingHandler {
+        async fn handle(&self, _ctx: PeerContext, _message: GetUpdatedDataRequest) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct Peers {
+        server: Connection,
+        client: Connection,
+        handled: CountingHandler,
+    }
+
+    async fn connect(auth: OnionAuth, prover: &OnionAuthProver) -> Peers {
+        let server_router = Arc::new(P2PMessageRouter::new());
+        auth.register(&server_router).await;
+        let handled = CountingHandler::default();
+        server_router.register(handled.clone()).await;
+
+        let client_router = Arc::new(P2PMessageRouter::new());
+        prover.register(&client_router).await;
+
+        let (local, remote) = tokio::io::duplex(4096);
+        Peers {
+            server: Connection::spawn(local, None, CodecConfig::default(), server_router),
+            client: Connection::spawn(remote, None, CodecConfig::default(), client_router),
+            handled,
+        }
+    }
+
+    async fn authenticated(connection: &Connection) -> Option<NodeAddress> {
+        loop {
+            let state = connection.state().await;
+            if state.authenticated_address.is_some() || connection.is_closed() {
+                return state.authenticated_address;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    fn get_updated_data(sender: &NodeAddress) -> NetworkEnvelope {
+        let request = GetUpdatedDataRequest {
+            sender_node_address: Some(sender.into()),
+            nonce: 1,
+            ..Default::default()
+        };
+        NetworkEnvelope {
+            message_version: "0X".into(),
+            message: Some(request.into_envelope_message()),
+        }
+    }
+
+    async fn settle() {
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn proven_peers_get_through_and_spoofers_are_closed() {
+        let prover = OnionAuthProver::new(Arc::new(OnionIdentity::generate()), 9999, "0X");
+        let peers = connect(OnionAuth::required(), &prover).await;
+
+        prover.start(&peers.client).await.unwrap();
+        assert_eq!(authenticated(&peers.server).await, Some(prover.node_address.clone()));
+
+        peers.client.send(get_updated_data(&prover.node_address)).await.unwrap();
+        settle().await;
+        assert_eq!(peers.handled.0.load(Ordering::SeqCst), 1);
+
+        let other = OnionAuthProver::new(Arc::new(OnionIdentity::generate()), 9999, "0X");
+        peers.client.send(get_updated_data(&other.node_address)).await.unwrap();
+        peers.server.finished().await;
+        assert_eq!(peers.handled.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unproven_senders_are_rejected_when_required() {
+        let prover = OnionAuthProver::new(Arc::new(OnionIdentity::generate()), 9999, "0X");
+        let peers = connect(OnionAuth::required(), &prover).await;
+
+        peers.client.send(get_updated_data(&prover.node_address)).await.unwrap();
+        settle().await;
+        assert_eq!(peers.handled.0.load(Ordering::SeqCst), 0);
+        assert!(!peers.server.is_closed());
+    }
+
+    #[tokio::test]
+    async fn unproven_senders_pass_when_optional() {
+        let prover = OnionAuthProver::new(Arc::new(OnionIdentity::generate()), 9999, "0X");
+        let peers = connect(OnionAuth::optional(), &prover).await;
+
+        peers.client.send(get_updated_data(&prover.node_address)).await.unwrap();
+        settle().await;
+        assert_eq!(peers.handled.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn claiming_someone_elses_address_fails_the_challenge() {
+        let impostor = OnionAuthProver {
+            node_address: OnionAuthProver::new(Arc::new(OnionIdentity::generate()), 9999, "0X").node_address,
+            ..OnionAuthProver::new(Arc::new(OnionIdentity::generate()), 9999, "0X")
+        };
+        let peers = connect(OnionAuth::required(), &impostor).await;
+
+        impostor.start(&peers.client).await.unwrap();
+        assert_eq!(authenticated(&peers.server).await, None);
+        assert!(peers.server.is_closed());
+    }
+}
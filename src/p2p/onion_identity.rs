@@ -1,10 +1,124 @@
-use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier, SECRET_KEY_LENGTH};
-use sha3::{Digest, Sha3_256};
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{ExpandedSecretKey, Keypair, PublicKey, SecretKey, Signature, Signer};
 use rand::rngs::OsRng;
-use data_encoding::BASE32_NOPAD;
+use std::fs;
+use std::path::Path;
+use crate::p2p::address::{AddressError, OnionAddress};
 
+/// File headers Tor writes in a hidden service directory, NUL-padded to 32 bytes
+const SECRET_KEY_HEADER: &[u8; 32] = b"== ed25519v1-secret: type0 ==\0\0\0";
+const PUBLIC_KEY_HEADER: &[u8; 32] = b"== ed25519v1-public: type0 ==\0\0\0";
+
+/// Our onion service key.
+///
+/// Tor only stores the expanded secret key, from which the original seed can't be recovered,
+/// so that is what we keep too. Saved and loaded as a Tor hidden service directory, so existing
+/// Haveno seed node directories can be moved to the Rust node and back.
 pub struct OnionIdentity {
-    pub keypair: Keypair,
+    secret: ExpandedSecretKey,
+    pub public: PublicKey,
+}
+
+impl OnionIdentity {
+    pub fn generate() -> Self {
+        Self::from_keypair(&generate_keypair())
+    }
+
+    pub fn from_keypair(keypair: &Keypair) -> Self {
+        Self {
+            secret: ExpandedSecretKey::from(&keypair.secret),
+            public: keypair.public,
+        }
+    }
+
+    /// From the 64-byte expanded secret key Tor uses
+    pub fn from_expanded_secret_key(bytes: &[u8]) -> Result<Self> {
+        let secret = ExpandedSecretKey::from_bytes(bytes)
+            .map_err(|e| anyhow::anyhow!("Invalid expanded secret key: {e}"))?;
+        let public = PublicKey::from(&secret);
+        Ok(Self { secret, public })
+    }
+
+    pub fn expanded_secret_key(&self) -> [u8; 64] {
+        self.secret.to_bytes()
+    }
+
+    pub fn onion_address(&self) -> OnionAddress {
+        OnionAddress::from_public_key(self.public)
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.secret.sign(message, &self.public)
+    }
+
+    /// Load the identity from a Tor hidden service directory. `hs_ed25519_public_key` and
+    /// `hostname` are optional, but must match the secret key when present.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let secret_path = dir.join("hs_ed25519_secret_key");
+        let secret_file = fs::read(&secret_path).with_context(|| format!("Failed to read {}", secret_path.display()))?;
+        let Some(secret) = secret_file.strip_prefix(SECRET_KEY_HEADER.as_slice()).filter(|key| key.len() == 64) else {
+            bail!("{} is not a Tor ed25519 secret key", secret_path.display());
+        };
+        let identity = Self::from_expanded_secret_key(secret)?;
+
+        let public_path = dir.join("hs_ed25519_public_key");
+        if let Ok(public_file) = fs::read(&public_path) {
+            if public_file.strip_prefix(PUBLIC_KEY_HEADER.as_slice()) != Some(identity.public.as_bytes().as_slice()) {
+                bail!("{} does not match the secret key", public_path.display());
+            }
+        }
+
+        let hostname_path = dir.join("hostname");
+        if let Ok(hostname) = fs::read_to_string(&hostname_path) {
+            if hostname.trim() != identity.onion_address().to_string() {
+                bail!("{} says {} but the key is for {}", hostname_path.display(), hostname.trim(), identity.onion_address());
+            }
+        }
+
+        Ok(identity)
+    }
+
+    /// Write the identity as a Tor hidden service directory, readable by us only as Tor requires
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let mut secret_file = SECRET_KEY_HEADER.to_vec();
+        secret_file.extend_from_slice(&self.expanded_secret_key());
+        let mut public_file = PUBLIC_KEY_HEADER.to_vec();
+        public_file.extend_from_slice(self.public.as_bytes());
+
+        write_private(&dir.join("hs_ed25519_secret_key"), &secret_file)?;
+        write_private(&dir.join("hs_ed25519_public_key"), &public_file)?;
+        write_private(&dir.join("hostname"), format!("{}\n", self.onion_address()).as_bytes())?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(())
+    }
+
+    /// Load the identity from `dir`, or generate and save one there on first run
+    pub fn load_or_generate(dir: &Path) -> Result<Self> {
+        if dir.join("hs_ed25519_secret_key").exists() {
+            return Self::load(dir);
+        }
+        let identity = Self::generate();
+        identity.save(dir)?;
+        println!("🧅 Generated onion identity {}", identity.onion_address());
+        Ok(identity)
+    }
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
 }
 
 pub fn generate_keypair() -> Keypair {
@@ -13,23 +127,7 @@ pub fn generate_keypair() -> Keypair {
 }
 
 pub fn get_onion_address(public_key: &PublicKey) -> String {
-    let pub_bytes = public_key.to_bytes();
-
-    // Checksum = H(".onion checksum" || pubkey || version)[:2]
-    let mut hasher = Sha3_256::new();
-    hasher.update(b".onion checksum");
-    hasher.update(&pub_bytes);
-    hasher.update(&[0x03]); // version byte
-    let hash = hasher.finalize();
-    let checksum = &hash[0..2];
-
-    // onion_address = BASE32(pubkey || checksum || version)
-    let mut onion_bytes = vec![];
-    onion_bytes.extend_from_slice(&pub_bytes);
-    onion_bytes.extend_from_slice(checksum);
-    onion_bytes.push(0x03); // version
-
-    BASE32_NOPAD.encode(&onion_bytes).to_lowercase() + ".onion"
+    OnionAddress::from_public_key(*public_key).to_string()
 }
 
 pub fn sign_nonce(secret_key_bytes: &[u8; 32], nonce: &[u8]) -> Vec<u8> {
@@ -39,18 +137,79 @@ pub fn sign_nonce(secret_key_bytes: &[u8; 32], nonce: &[u8]) -> Vec<u8> {
     keypair.sign(nonce).to_bytes().to_vec()
 }
 
-pub fn verify_signature(onion_address: &str, nonce: &[u8], signature_bytes: &[u8]) -> bool {
-    // Extract pubkey from onion address
-    let addr = onion_address.trim_end_matches(".onion").to_uppercase();
-    let decoded = BASE32_NOPAD.decode(addr.as_bytes()).expect("Invalid base32");
+/// Check that `signature_bytes` over `nonce` was made by the key behind `onion_address`.
+/// Malformed addresses and signatures are errors rather than panics, as both come from peers.
+pub fn verify_signature(onion_address: &str, nonce: &[u8], signature_bytes: &[u8]) -> Result<(), AddressError> {
+    onion_address.parse::<OnionAddress>()?.verify(nonce, signature_bytes)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("onion-identity-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn round_trips_through_tor_key_files() {
+        let dir = temp_dir();
+        let identity = OnionIdentity::generate();
+        identity.save(&dir).unwrap();
 
-    if decoded.len() < 32 {
-        return false;
+        let secret_file = fs::read(dir.join("hs_ed25519_secret_key")).unwrap();
+        assert_eq!(secret_file.len(), 96);
+        assert!(secret_file.starts_with(b"== ed25519v1-secret: type0 =="));
+        assert_eq!(fs::read(dir.join("hs_ed25519_public_key")).unwrap().len(), 64);
+        assert_eq!(fs::read_to_string(dir.join("hostname")).unwrap(), format!("{}\n", identity.onion_address()));
+
+        let loaded = OnionIdentity::load(&dir).unwrap();
+        assert_eq!(loaded.public, identity.public);
+        assert_eq!(loaded.expanded_secret_key(), identity.expanded_secret_key());
+
+        // A key loaded without its seed still signs for its address
+        let signature = loaded.sign(b"nonce");
+        assert!(verify_signature(&loaded.onion_address().to_string(), b"nonce", &signature.to_bytes()).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expanded_key_matches_the_seed() {
+        let keypair = generate_keypair();
+        let identity = OnionIdentity::from_keypair(&keypair);
+        let from_expanded = OnionIdentity::from_expanded_secret_key(&identity.expanded_secret_key()).unwrap();
+        assert_eq!(from_expanded.public, keypair.public);
+        assert_eq!(from_expanded.sign(b"message"), keypair.sign(b"message"));
+    }
+
+    #[test]
+    fn verify_signature_reports_bad_input() {
+        let identity = OnionIdentity::generate();
+        let address = identity.onion_address().to_string();
+        let signature = identity.sign(b"nonce").to_bytes();
+
+        assert_eq!(verify_signature(&address, b"other", &signature), Err(AddressError::SignatureMismatch));
+        assert_eq!(verify_signature(&address, b"nonce", &signature[..10]), Err(AddressError::InvalidSignature));
+        assert!(matches!(verify_signature("not-an-onion", b"nonce", &signature), Err(AddressError::NotOnion(_))));
     }
 
-    let pubkey_bytes = &decoded[0..32];
-    let public_key = PublicKey::from_bytes(pubkey_bytes).expect("Invalid public key");
-    let signature = Signature::from_bytes(signature_bytes).expect("Invalid signature");
+    #[test]
+    fn rejects_a_mismatched_hostname() {
+        let dir = temp_dir();
+        OnionIdentity::generate().save(&dir).unwrap();
+        let other = OnionIdentity::generate();
+        fs::write(dir.join("hostname"), format!("{}\n", other.onion_address())).unwrap();
+
+        assert!(OnionIdentity::load(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 
-    public_key.verify(nonce, &signature).is_ok()
-}
\ No newline at end of file
+    #[test]
+    fn generates_once_then_reloads() {
+        let dir = temp_dir();
+        let first = OnionIdentity::load_or_generate(&dir).unwrap();
+        let second = OnionIdentity::load_or_generate(&dir).unwrap();
+        assert_eq!(first.onion_address(), second.onion_address());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+use openssl::sha::sha256;
+use prost::Message;
+use std::fmt;
+use std::time::Duration;
+use crate::crypto::sig;
+use crate::generated::io_haveno_protobuffer::{
+    storage_payload::Message as PayloadMessage, DataAndSeqNrPair, ProtectedMailboxStorageEntry, ProtectedStorageEntry,
+    StoragePayload,
+};
+
+/// How far ahead of our clock a peer's creation timestamp may be
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Why a `ProtectedStorageEntry` was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryError {
+    MissingPayload,
+    /// The payload doesn't carry the key that owns it
+    MissingOwnerKey,
+    /// The entry is signed by another key than the one the payload names as its owner
+    OwnerMismatch,
+    /// A mailbox entry addressed to someone else than its payload's receiver
+    ReceiverMismatch,
+    InvalidSignature,
+    /// We already have this or a later version of the entry
+    StaleSequenceNumber { received: i32, known: i32 },
+    /// Created more than `MAX_CLOCK_SKEW` after our current time
+    CreatedInFuture { ahead: Duration },
+    /// Older than its payload type's time to live
+    Expired { age: Duration },
+    /// A refresh for an entry we don't have
+    UnknownEntry,
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingPayload => write!(f, "entry has no storage payload"),
+            Self::MissingOwnerKey => write!(f, "payload has no owner public key"),
+            Self::OwnerMismatch => write!(f, "entry owner is not the payload's owner"),
+            Self::ReceiverMismatch => write!(f, "mailbox entry receiver is not the payload's owner"),
+            Self::InvalidSignature => write!(f, "signature does not verify under the owner key"),
+            Self::StaleSequenceNumber { received, known } => {
+                write!(f, "sequence number {received} is not above the known {known}")
+            }
+            Self::CreatedInFuture { ahead } => write!(f, "created {}s in the future", ahead.as_secs()),
+            Self::Expired { age } => write!(f, "expired, created {}s ago", age.as_secs()),
+            Self::UnknownEntry => write!(f, "no such entry"),
+        }
+    }
+}
+
+impl std::error::Error for EntryError {}
+
+/// What Haveno keys stored entries by, the SHA-256 of the serialized `StoragePayload`
+pub fn payload_hash(payload: &StoragePayload) -> [u8; 32] {
+    sha256(&payload.encode_to_vec())
+}
+
+/// The signature key the payload names as its owner, `getOwnerPubKey` in Haveno
+pub fn payload_owner_key(payload: &StoragePayload) -> Option<&[u8]> {
+    let key = match payload.message.as_ref()? {
+        PayloadMessage::Alert(alert) => &alert.owner_pub_key_bytes,
+        PayloadMessage::Filter(filter) => &filter.owner_pub_key_bytes,
+        PayloadMessage::MailboxStoragePayload(mailbox) => &mailbox.owner_pub_key_bytes,
+        PayloadMessage::Arbitrator(agent) => &agent.pub_key_ring.as_ref()?.signature_pub_key_bytes,
+        PayloadMessage::Mediator(agent) => &agent.pub_key_ring.as_ref()?.signature_pub_key_bytes,
+        PayloadMessage::RefundAgent(agent) => &agent.pub_key_ring.as_ref()?.signature_pub_key_bytes,
+        PayloadMessage::OfferPayload(offer) => &offer.pub_key_ring.as_ref()?.signature_pub_key_bytes,
+    };
+    Some(key).filter(|key| !key.is_empty())
+}
+
+/// How long Haveno keeps each payload type without a refresh
+pub fn time_to_live(payload: &StoragePayload) -> Duration {
+    const DAY: u64 = 24 * 60 * 60;
+    let secs = match payload.message {
+        Some(PayloadMessage::OfferPayload(_)) => 9 * 60,
+        Some(PayloadMessage::Filter(_)) => 180 * DAY,
+        Some(PayloadMessage::Alert(_)) => 90 * DAY,
+        Some(PayloadMessage::MailboxStoragePayload(_)) => 15 * DAY,
+        Some(PayloadMessage::Arbitrator(_) | PayloadMessage::Mediator(_) | PayloadMessage::RefundAgent(_)) => 10 * DAY,
+        None => 0,
+    };
+    Duration::from_secs(secs)
+}
+
+/// What the owner signs, so a signature only holds for one version of the entry
+pub fn signed_hash(payload: &StoragePayload, sequence_number: i32) -> [u8; 32] {
+    let pair = DataAndSeqNrPair {
+        payload: Some(payload.clone()),
+        sequence_number,
+    };
+    sha256(&pair.encode_to_vec())
+}
+
+/// Check that the entry is signed by `owner_key`. Returns the payload hash to key it by.
+pub fn verify_owner_signature(entry: &ProtectedStorageEntry, owner_key: &[u8]) -> Result<[u8; 32], EntryError> {
+    let payload = entry.storage_payload.as_ref().ok_or(EntryError::MissingPayload)?;
+    if entry.owner_pub_key_bytes != owner_key {
+        return Err(EntryError::OwnerMismatch);
+    }
+    sig::verify(owner_key, &signed_hash(payload, entry.sequence_number), &entry.signature)
+        .map_err(|_| EntryError::InvalidSignature)?;
+    Ok(payload_hash(payload))
+}
+
+/// Check everything about an entry that doesn't depend on what we already store: the owner,
+/// the signature and the creation time. Returns the payload hash to key the entry by.
+pub fn validate_entry(entry: &ProtectedStorageEntry, owner_key: &[u8], now_millis: i64) -> Result<[u8; 32], EntryError> {
+    let hash = verify_owner_signature(entry, owner_key)?;
+    let payload = entry.storage_payload.as_ref().ok_or(EntryError::MissingPayload)?;
+
+    let age = now_millis - entry.creation_time_stamp;
+    if age < 0 {
+        let ahead = Duration::from_millis(age.unsigned_abs());
+        if ahead > MAX_CLOCK_SKEW {
+            return Err(EntryError::CreatedInFuture { ahead });
+        }
+    } else {
+        let age = Duration::from_millis(age as u64);
+        if age > time_to_live(payload) {
+            return Err(EntryError::Expired { age });
+        }
+    }
+
+    Ok(hash)
+}
+
+/// Validate an entry for an add: it must be owned by the key its payload names
+pub fn validate_protected_entry(entry: &ProtectedStorageEntry, now_millis: i64) -> Result<[u8; 32], EntryError> {
+    let payload = entry.storage_payload.as_ref().ok_or(EntryError::MissingPayload)?;
+    let owner = payload_owner_key(payload).ok_or(EntryError::MissingOwnerKey)?;
+    validate_entry(entry, owner, now_millis)
+}
+
+/// Validate a mailbox entry for an add. The sender adds it, so it must be signed with the
+/// sender's key from the payload, while the payload is owned by the receiver.
+pub fn validate_mailbox_entry(entry: &ProtectedMailboxStorageEntry, now_millis: i64) -> Result<[u8; 32], EntryError> {
+    let inner = entry.entry.as_ref().ok_or(EntryError::MissingPayload)?;
+    let Some(PayloadMessage::MailboxStoragePayload(mailbox)) = inner.storage_payload.as_ref().and_then(|p| p.message.as_ref()) else {
+        return Err(EntryError::MissingPayload);
+    };
+    if mailbox.sender_pub_key_for_add_operation_bytes.is_empty() || mailbox.owner_pub_key_bytes.is_empty() {
+        return Err(EntryError::MissingOwnerKey);
+    }
+    if entry.receivers_pub_key_bytes != mailbox.owner_pub_key_bytes {
+        return Err(EntryError::ReceiverMismatch);
+    }
+    validate_entry(inner, &mailbox.sender_pub_key_for_add_operation_bytes, now_millis)
+}
+
+/// Sequence numbers only go up: an entry must be newer than the version we know
+pub fn check_sequence_number(received: i32, known: Option<i32>) -> Result<(), EntryError> {
+    match known {
+        Some(known) if received <= known => Err(EntryError::StaleSequenceNumber { received, known }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builders::{add_data, filter};
+    use crate::crypto::key_ring::KeyRing;
+    use crate::generated::io_haveno_protobuffer::storage_entry_wrapper::Message as WrapperMessage;
+
+    fn signed_filter_entry(key_ring: &KeyRing) -> ProtectedStorageEntry {
+        let filter = filter::build_signed_filter(key_ring).unwrap();
+        let message = add_data::build_add_data_message(PayloadMessage::Filter(filter), key_ring).unwrap();
+        match message.entry.unwrap().message.unwrap() {
+            WrapperMessage::ProtectedStorageEntry(entry) => entry,
+            other => panic!("unexpected entry {other:?}"),
+        }
+    }
+
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+
+    #[test]
+    fn accepts_entries_we_build() {
+        let entry = signed_filter_entry(&KeyRing::generate().unwrap());
+        let hash = validate_protected_entry(&entry, now()).unwrap();
+        assert_eq!(hash, payload_hash(entry.storage_payload.as_ref().unwrap()));
+    }
+
+    #[test]
+    fn rejects_tampered_and_foreign_entries() {
+        let key_ring = KeyRing::generate().unwrap();
+        let entry = signed_filter_entry(&key_ring);
+
+        let mut bumped = entry.clone();
+        bumped.sequence_number += 1;
+        assert_eq!(validate_protected_entry(&bumped, now()), Err(EntryError::InvalidSignature));
+
+        let mut foreign = entry.clone();
+        foreign.owner_pub_key_bytes = KeyRing::generate().unwrap().signature().public_key_der().unwrap();
+        assert_eq!(validate_protected_entry(&foreign, now()), Err(EntryError::OwnerMismatch));
+
+        let mut unowned = entry;
+        if let Some(PayloadMessage::Filter(filter)) = unowned.storage_payload.as_mut().and_then(|p| p.message.as_mut()) {
+            filter.owner_pub_key_bytes.clear();
+        }
+        assert_eq!(validate_protected_entry(&unowned, now()), Err(EntryError::MissingOwnerKey));
+    }
+
+    #[test]
+    fn checks_creation_time() {
+        let entry = signed_filter_entry(&KeyRing::generate().unwrap());
+        let created = entry.creation_time_stamp;
+
+        assert!(validate_protected_entry(&entry, created - 60_000).is_ok());
+        assert!(matches!(
+            validate_protected_entry(&entry, created - 10 * 60_000),
+            Err(EntryError::CreatedInFuture { .. })
+        ));
+        assert!(matches!(
+            validate_protected_entry(&entry, created + 181 * 24 * 60 * 60_000),
+            Err(EntryError::Expired { .. })
+        ));
+    }
+
+    #[test]
+    fn sequence_numbers_must_increase() {
+        assert!(check_sequence_number(1, None).is_ok());
+        assert!(check_sequence_number(2, Some(1)).is_ok());
+        assert_eq!(check_sequence_number(1, Some(1)), Err(EntryError::StaleSequenceNumber { received: 1, known: 1 }));
+        assert!(check_sequence_number(0, Some(1)).is_err());
+    }
+}
@@ -1,49 +1,278 @@
 use async_trait::async_trait;
 use anyhow::Result;
 use std::collections::HashMap;
+use std::marker::PhantomData;
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use crate::generated::io_haveno_protobuffer::NetworkEnvelope;
+use crate::p2p::connection::Connection;
 use crate::p2p::context::PeerContext;
+use crate::p2p::middleware::{MiddlewareAction, P2PMiddleware};
+use crate::utils::network::ack::ack_source;
+use crate::utils::network::envelope::EnvMsg;
+use crate::utils::network::message::{message_name, message_tag, EnvelopeMessage};
 
+/// Handles one message type, e.g. `impl P2PMessageHandler<AddDataMessage> for AddDataMessageHandler`
 #[async_trait]
-pub trait P2PMessageHandler: Send + Sync {
-    async fn handle(&self, ctx: PeerContext) -> Result<()>;
+pub trait P2PMessageHandler<M: EnvelopeMessage>: Send + Sync {
+    async fn handle(&self, ctx: PeerContext, message: M) -> Result<()>;
 }
 
+/// A handler with its message type erased, so handlers for different types share one table
+#[async_trait]
+trait ErasedHandler: Send + Sync {
+    async fn handle(&self, ctx: PeerContext, message: EnvMsg) -> Result<()>;
+}
+
+struct TypedHandler<M, H> {
+    handler: H,
+    _message: PhantomData<fn(M)>,
+}
+
+#[async_trait]
+impl<M, H> ErasedHandler for TypedHandler<M, H>
+where
+    M: EnvelopeMessage,
+    H: P2PMessageHandler<M>,
+{
+    async fn handle(&self, ctx: PeerContext, message: EnvMsg) -> Result<()> {
+        match M::from_envelope_message(message) {
+            Some(message) => self.handler.handle(ctx, message).await,
+            // Handlers are keyed by `M::TAG`, so only an `M` ever gets here
+            None => unreachable!("{} handler got another message type", M::NAME),
+        }
+    }
+}
+
+/// Routes envelopes to handlers by message type, through the middleware chain
 pub struct P2PMessageRouter {
-    handlers: RwLock<HashMap<String, Arc<dyn P2PMessageHandler>>>,
+    handlers: RwLock<HashMap<u32, Arc<dyn ErasedHandler>>>,
+    middleware: RwLock<Vec<Arc<dyn P2PMiddleware>>>,
+}
+
+impl Default for P2PMessageRouter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl P2PMessageRouter {
     pub fn new() -> Self {
         Self {
             handlers: RwLock::new(HashMap::new()),
+            middleware: RwLock::new(Vec::new()),
         }
     }
 
-    pub async fn register<T: Into<String>>(
-        &self,
-        message_type: T,
-        handler: impl P2PMessageHandler + 'static,
-    ) {
-        let mut handlers = self.handlers.write().await;
-        handlers.insert(message_type.into(), Arc::new(handler));
+    /// Append `middleware` to the chain. It runs after everything registered before it.
+    pub async fn add_middleware(&self, middleware: impl P2PMiddleware + 'static) {
+        self.middleware.write().await.push(Arc::new(middleware));
+    }
+
+    /// Register `handler` for its message type, replacing any earlier handler for that type
+    pub async fn register<M, H>(&self, handler: H)
+    where
+        M: EnvelopeMessage,
+        H: P2PMessageHandler<M> + 'static,
+    {
+        let handler = TypedHandler {
+            handler,
+            _message: PhantomData,
+        };
+        self.handlers.write().await.insert(M::TAG, Arc::new(handler));
     }
 
-    pub async fn dispatch(&self, ctx: PeerContext) -> Result<()> {
-        let handlers = self.handlers.read().await;
-        let message_name = ctx.envelope.message
-            .as_ref()
-            .map(|m| format!("{:?}", m))
-            .unwrap_or_else(|| "None".into());
+    pub async fn dispatch(&self, connection: Connection, envelope: NetworkEnvelope) -> Result<()> {
+        let Some(message) = envelope.message else {
+            println!("⚠️ Envelope without a message on connection {}", connection.id());
+            return Ok(());
+        };
 
-        for (name, handler) in handlers.iter() {
-            if message_name.contains(name) {
-                return handler.handle(ctx).await;
+        let name = message_name(&message);
+        let middleware = self.middleware.read().await.clone();
+        let mut ctx = PeerContext::new(connection, envelope.message_version);
+        ctx.ack_source = ack_source(&message);
+        for m in &middleware {
+            match m.before(&mut ctx, &message).await {
+                MiddlewareAction::Continue => {}
+                MiddlewareAction::Reject(reason) => {
+                    println!("🚫 Rejected {name} on connection {}: {reason}", ctx.connection.id());
+                    return Ok(());
+                }
+                MiddlewareAction::Close(reason) => {
+                    println!("🚫 Closing connection {} after {name}: {reason}", ctx.connection.id());
+                    ctx.connection.close_with_reason(&reason).await;
+                    return Ok(());
+                }
             }
         }
 
-        println!("⚠️ No handler found for message: {}", message_name);
-        Ok(())
+        let handler = self.handlers.read().await.get(&message_tag(&message)).cloned();
+        let Some(handler) = handler else {
+            println!("⚠️ No handler found for message: {name}");
+            return Ok(());
+        };
+
+        let result = handler.handle(ctx.clone(), message).await;
+        for m in middleware.iter().rev() {
+            m.after(&ctx, name, &result).await;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::{
+        AddDataMessage, AddPersistableNetworkPayloadMessage, DepositRequest, NodeAddress,
+    };
+    use crate::p2p::middleware::{AutoAck, MessageVersionCheck};
+    use crate::utils::network::codec::{CodecConfig, EnvelopeStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone, Default)]
+    struct CountingHandler(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl P2PMessageHandler<AddDataMessage> for CountingHandler {
+        async fn handle(&self, _ctx: PeerContext, _message: AddDataMessage) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn envelope(message_version: &str, message: EnvMsg) -> NetworkEnvelope {
+        NetworkEnvelope {
+            message_version: message_version.into(),
+            message: Some(message),
+        }
     }
-}
\ No newline at end of file
+
+    /// Records the order hooks run in and what the handler saw
+    struct Recorder {
+        name: &'static str,
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl P2PMiddleware for Recorder {
+        async fn before(&self, ctx: &mut PeerContext, _message: &EnvMsg) -> MiddlewareAction {
+            self.log.lock().unwrap().push(format!("before {}", self.name));
+            ctx.annotate("last", self.name);
+            MiddlewareAction::Continue
+        }
+
+        async fn after(&self, ctx: &PeerContext, message_name: &'static str, result: &Result<()>) {
+            let line = format!("after {} {message_name} {} {:?}", self.name, result.is_ok(), ctx.annotation("last"));
+            self.log.lock().unwrap().push(line);
+        }
+    }
+
+    #[tokio::test]
+    async fn middleware_wraps_handler_in_order() {
+        let router = Arc::new(P2PMessageRouter::new());
+        let handler = CountingHandler::default();
+        router.register(handler.clone()).await;
+
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        router.add_middleware(Recorder { name: "a", log: log.clone() }).await;
+        router.add_middleware(Recorder { name: "b", log: log.clone() }).await;
+
+        let (local, _remote) = tokio::io::duplex(64);
+        let connection = Connection::spawn(local, None, CodecConfig::default(), router.clone());
+        let add_data = AddDataMessage { entry: None }.into_envelope_message();
+        router.dispatch(connection, envelope("0X", add_data)).await.unwrap();
+
+        assert_eq!(handler.0.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "before a",
+                "before b",
+                "after b AddDataMessage true Some(\"b\")",
+                "after a AddDataMessage true Some(\"b\")",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn middleware_can_close_the_connection() {
+        let router = Arc::new(P2PMessageRouter::new());
+        let handler = CountingHandler::default();
+        router.register(handler.clone()).await;
+        router.add_middleware(MessageVersionCheck::new("0X")).await;
+
+        let (local, remote) = tokio::io::duplex(1024);
+        let connection = Connection::spawn(local, None, CodecConfig::default(), router.clone());
+        let add_data = AddDataMessage { entry: None }.into_envelope_message();
+        router.dispatch(connection.clone(), envelope("1X", add_data)).await.unwrap();
+
+        assert_eq!(handler.0.load(Ordering::SeqCst), 0);
+        assert!(connection.is_closed());
+
+        let mut peer = EnvelopeStream::new(remote);
+        let close = peer.read_envelope().await.unwrap().unwrap();
+        assert!(matches!(close.message, Some(EnvMsg::CloseConnectionMessage(m)) if m.reason == "RULE_VIOLATION"));
+    }
+
+    struct FailingDepositHandler;
+
+    #[async_trait]
+    impl P2PMessageHandler<DepositRequest> for FailingDepositHandler {
+        async fn handle(&self, _ctx: PeerContext, _message: DepositRequest) -> Result<()> {
+            anyhow::bail!("deposit tx is invalid")
+        }
+    }
+
+    #[tokio::test]
+    async fn auto_ack_reports_handler_outcome() {
+        let router = Arc::new(P2PMessageRouter::new());
+        router.register(FailingDepositHandler).await;
+        let our_address = NodeAddress {
+            host_name: "example.onion".into(),
+            port: 9999,
+        };
+        router.add_middleware(AutoAck::new(our_address.clone())).await;
+
+        let (local, remote) = tokio::io::duplex(1024);
+        let connection = Connection::spawn(local, None, CodecConfig::default(), router.clone());
+        let request = DepositRequest {
+            trade_id: "trade-1".into(),
+            uid: "uid-1".into(),
+            ..Default::default()
+        };
+        let result = router.dispatch(connection, envelope("0X", request.into_envelope_message())).await;
+        assert!(result.is_err());
+
+        let mut peer = EnvelopeStream::new(remote);
+        let reply = peer.read_envelope().await.unwrap().unwrap();
+        assert_eq!(reply.message_version, "0X");
+        let Some(EnvMsg::AckMessage(ack)) = reply.message else {
+            panic!("expected an AckMessage, got {:?}", reply.message);
+        };
+        assert_eq!(ack.sender_node_address, Some(our_address));
+        assert_eq!(ack.source_msg_class_name, "DepositRequest");
+        assert_eq!(ack.source_uid, "uid-1");
+        assert!(!ack.success);
+        assert_eq!(ack.error_message, "deposit tx is invalid");
+    }
+
+    #[tokio::test]
+    async fn routes_on_message_type_not_name() {
+        let router = Arc::new(P2PMessageRouter::new());
+        let handler = CountingHandler::default();
+        router.register(handler.clone()).await;
+
+        let (local, _remote) = tokio::io::duplex(64);
+        let connection = Connection::spawn(local, None, CodecConfig::default(), router.clone());
+
+        let payload = AddPersistableNetworkPayloadMessage { payload: None }.into_envelope_message();
+        router.dispatch(connection.clone(), envelope("0X", payload)).await.unwrap();
+        assert_eq!(handler.0.load(Ordering::SeqCst), 0);
+
+        let add_data = AddDataMessage { entry: None }.into_envelope_message();
+        router.dispatch(connection, envelope("0X", add_data)).await.unwrap();
+        assert_eq!(handler.0.load(Ordering::SeqCst), 1);
+    }
+}
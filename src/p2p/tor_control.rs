@@ -0,0 +1,352 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose, Engine};
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use crate::p2p::listener::P2PListener;
+use crate::p2p::onion_identity::OnionIdentity;
+
+/// One reply from the control port: the status code and the text of each line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub status: u16,
+    pub lines: Vec<String>,
+}
+
+/// An asynchronous `650` event, e.g. `HS_DESC UPLOADED ...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorEvent {
+    pub name: String,
+    pub lines: Vec<String>,
+}
+
+/// A client for Tor's control protocol, used to publish our onion service.
+///
+/// Onion services added here are tied to the control connection: Tor removes them when it
+/// closes, so keep the client around for as long as the service should be reachable.
+pub struct TorControl<S> {
+    reader: BufReader<ReadHalf<S>>,
+    writer: WriteHalf<S>,
+    /// Events that arrived while waiting for a command's reply
+    events: VecDeque<TorEvent>,
+}
+
+impl TorControl<TcpStream> {
+    /// Connect to Tor's control port, usually `127.0.0.1:9051`
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await.context("Failed to connect to Tor's control port")?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> TorControl<S> {
+    pub fn new(stream: S) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Authenticate with whatever `PROTOCOLINFO` says Tor accepts: no auth, the cookie file,
+    /// or `password` if one is given
+    pub async fn authenticate(&mut self, password: Option<&str>) -> Result<()> {
+        let info = self.command("PROTOCOLINFO 1").await?;
+        let auth = info
+            .lines
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH "))
+            .ok_or_else(|| anyhow!("PROTOCOLINFO did not list auth methods"))?;
+        let methods: Vec<&str> = auth
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("METHODS="))
+            .map(|methods| methods.split(',').collect())
+            .unwrap_or_default();
+
+        let credentials = if methods.contains(&"NULL") {
+            String::new()
+        } else if let (Some(password), true) = (password, methods.contains(&"HASHEDPASSWORD")) {
+            quote(password)
+        } else if methods.contains(&"COOKIE") {
+            let path = auth
+                .split_once("COOKIEFILE=")
+                .map(|(_, rest)| unquote(rest))
+                .ok_or_else(|| anyhow!("PROTOCOLINFO did not name the cookie file"))?;
+            let cookie = std::fs::read(&path).with_context(|| format!("Failed to read Tor cookie {path}"))?;
+            hex::encode(cookie)
+        } else {
+            bail!("No supported Tor auth method in {methods:?}");
+        };
+
+        self.command(format!("AUTHENTICATE {credentials}").trim_end()).await?;
+        Ok(())
+    }
+
+    /// Publish the onion service for `identity`, forwarding `virtual_port` to `target`.
+    /// Returns the service id, the onion address without `.onion`.
+    pub async fn add_onion(&mut self, identity: &OnionIdentity, virtual_port: u16, target: SocketAddr) -> Result<String> {
+        // Tor takes the 64-byte expanded secret key, not the 32-byte seed
+        let key = general_purpose::STANDARD.encode(identity.expanded_secret_key());
+        let reply = self
+            .command(&format!("ADD_ONION ED25519-V3:{key} Port={virtual_port},{target}"))
+            .await?;
+
+        let service_id = reply
+            .lines
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .ok_or_else(|| anyhow!("ADD_ONION reply has no ServiceID"))?
+            .to_string();
+
+        let expected = identity.onion_address();
+        if expected.service_id() != service_id {
+            bail!("Tor published {service_id}.onion but our key is for {expected}");
+        }
+        Ok(service_id)
+    }
+
+    /// Publish `listener` as our onion service on `virtual_port`, which peers then reach as
+    /// `<address>.onion:<virtual_port>`
+    pub async fn publish_listener(&mut self, identity: &OnionIdentity, virtual_port: u16, listener: &P2PListener) -> Result<String> {
+        let mut target = listener.addr();
+        if target.ip().is_unspecified() {
+            target.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        let service_id = self.add_onion(identity, virtual_port, target).await?;
+        println!("🧅 Published {service_id}.onion:{virtual_port} -> {target}");
+        Ok(service_id)
+    }
+
+    pub async fn del_onion(&mut self, service_id: &str) -> Result<()> {
+        self.command(&format!("DEL_ONION {service_id}")).await?;
+        Ok(())
+    }
+
+    /// Subscribe to `events`, e.g. `HS_DESC` to follow descriptor uploads. Replaces any
+    /// earlier subscription.
+    pub async fn set_events(&mut self, events: &[&str]) -> Result<()> {
+        self.command(format!("SETEVENTS {}", events.join(" ")).trim_end()).await?;
+        Ok(())
+    }
+
+    /// The next event Tor sent, waiting for one if none is queued
+    pub async fn next_event(&mut self) -> Result<TorEvent> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            let reply = self.read_reply().await?;
+            if reply.status == 650 {
+                return Ok(event(reply));
+            }
+            println!("⚠️ Unexpected Tor control reply outside a command: {reply:?}");
+        }
+    }
+
+    /// Send `command` and wait for its reply, failing on anything but `250`
+    pub async fn command(&mut self, command: &str) -> Result<Reply> {
+        self.writer.write_all(format!("{command}\r\n").as_bytes()).await?;
+        self.writer.flush().await?;
+
+        loop {
+            let reply = self.read_reply().await?;
+            match reply.status {
+                650 => self.events.push_back(event(reply)),
+                250 => return Ok(reply),
+                status => {
+                    // Only the keyword, the rest may be a password or key
+                    let keyword = command.split_whitespace().next().unwrap_or_default();
+                    bail!("Tor refused {keyword}: {status} {}", reply.lines.join(" "));
+                }
+            }
+        }
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply> {
+        let mut status = None;
+        let mut lines = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if line.len() < 4 || !line.is_char_boundary(3) {
+                bail!("Malformed Tor control reply: {line:?}");
+            }
+            let code: u16 = line[..3].parse().with_context(|| format!("Malformed Tor control reply: {line:?}"))?;
+            if *status.get_or_insert(code) != code {
+                bail!("Tor control reply changed status mid-reply: {line:?}");
+            }
+
+            let text = line[4..].to_string();
+            match &line[3..4] {
+                " " => {
+                    lines.push(text);
+                    return Ok(Reply { status: code, lines });
+                }
+                "-" => lines.push(text),
+                "+" => {
+                    // Data follows up to a lone "."
+                    let mut data = vec![text];
+                    loop {
+                        let line = self.read_line().await?;
+                        if line == "." {
+                            break;
+                        }
+                        data.push(line.strip_prefix('.').unwrap_or(&line).to_string());
+                    }
+                    lines.push(data.join("\n"));
+                }
+                _ => bail!("Malformed Tor control reply: {line:?}"),
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            bail!("Tor closed the control connection");
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+fn event(reply: Reply) -> TorEvent {
+    let mut lines = reply.lines;
+    let first = lines.first_mut().map(std::mem::take).unwrap_or_default();
+    let (name, rest) = first.split_once(' ').unwrap_or((&first, ""));
+    let name = name.to_string();
+    if let Some(line) = lines.first_mut() {
+        *line = rest.to_string();
+    }
+    TorEvent { name, lines }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The quoted string at the start of `value`
+fn unquote(value: &str) -> String {
+    let mut unquoted = String::new();
+    let mut chars = value.strip_prefix('"').unwrap_or(value).chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, DuplexStream};
+    use tokio::task::JoinHandle;
+
+    /// A control port that expects each command to start with the given prefix and
+    /// answers with the scripted reply
+    fn fake_control_port(script: Vec<(&'static str, String)>) -> (DuplexStream, JoinHandle<Vec<String>>) {
+        let (client, server) = tokio::io::duplex(4096);
+        let task = tokio::spawn(async move {
+            let (reader, mut writer) = tokio::io::split(server);
+            let mut reader = BufReader::new(reader);
+            let mut received = Vec::new();
+            for (expected, reply) in script {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                let line = line.trim_end().to_string();
+                assert!(line.starts_with(expected), "expected {expected}, got {line}");
+                writer.write_all(reply.as_bytes()).await.unwrap();
+                received.push(line);
+            }
+            received
+        });
+        (client, task)
+    }
+
+    #[tokio::test]
+    async fn publishes_our_onion_service() {
+        let identity = OnionIdentity::generate();
+        let service_id = identity.onion_address().service_id();
+        let (stream, tor) = fake_control_port(vec![
+            ("PROTOCOLINFO 1", "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250 OK\r\n".into()),
+            ("AUTHENTICATE", "250 OK\r\n".into()),
+            ("SETEVENTS HS_DESC", "250 OK\r\n".into()),
+            (
+                "ADD_ONION ED25519-V3:",
+                format!("650 HS_DESC CREATED {service_id}\r\n250-ServiceID={service_id}\r\n250 OK\r\n"),
+            ),
+            ("DEL_ONION", "250 OK\r\n".into()),
+        ]);
+
+        let mut tor_control = TorControl::new(stream);
+        tor_control.authenticate(None).await.unwrap();
+        tor_control.set_events(&["HS_DESC"]).await.unwrap();
+        let target: SocketAddr = "127.0.0.1:2002".parse().unwrap();
+        assert_eq!(tor_control.add_onion(&identity, 9999, target).await.unwrap(), service_id);
+
+        // The event that came in with the ADD_ONION reply is kept for us
+        let event = tor_control.next_event().await.unwrap();
+        assert_eq!(event.name, "HS_DESC");
+        assert_eq!(event.lines, [format!("CREATED {service_id}")]);
+
+        tor_control.del_onion(&service_id).await.unwrap();
+
+        let received = tor.await.unwrap();
+        let add_onion = &received[3];
+        assert!(add_onion.ends_with(" Port=9999,127.0.0.1:2002"));
+        let key = add_onion.trim_start_matches("ADD_ONION ED25519-V3:").split(' ').next().unwrap();
+        let expanded = general_purpose::STANDARD.decode(key).unwrap();
+        assert_eq!(expanded, identity.expanded_secret_key());
+        assert_eq!(received[4], format!("DEL_ONION {service_id}"));
+    }
+
+    #[tokio::test]
+    async fn authenticates_with_the_cookie_file() {
+        let cookie_path = std::env::temp_dir().join(format!("tor-control-test-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&cookie_path, [0xab; 32]).unwrap();
+        let (stream, tor) = fake_control_port(vec![
+            (
+                "PROTOCOLINFO 1",
+                format!(
+                    "250-PROTOCOLINFO 1\r\n250-AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE=\"{}\"\r\n250 OK\r\n",
+                    cookie_path.display()
+                ),
+            ),
+            ("AUTHENTICATE", "250 OK\r\n".into()),
+        ]);
+
+        TorControl::new(stream).authenticate(None).await.unwrap();
+        std::fs::remove_file(&cookie_path).unwrap();
+
+        let received = tor.await.unwrap();
+        assert_eq!(received[1], format!("AUTHENTICATE {}", "ab".repeat(32)));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_service_id_for_another_key() {
+        let (stream, _tor) = fake_control_port(vec![(
+            "ADD_ONION",
+            "250-ServiceID=someoneelse\r\n250 OK\r\n".into(),
+        )]);
+
+        let result = TorControl::new(stream)
+            .add_onion(&OnionIdentity::generate(), 9999, "127.0.0.1:2002".parse().unwrap())
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn error_replies_fail_the_command() {
+        let (stream, _tor) = fake_control_port(vec![(
+            "AUTHENTICATE",
+            "515 Authentication failed: Password did not match\r\n".into(),
+        )]);
+
+        let error = TorControl::new(stream).command("AUTHENTICATE \"hunter2\"").await.unwrap_err();
+        assert!(error.to_string().contains("515"));
+        assert!(!error.to_string().contains("hunter2"));
+    }
+}
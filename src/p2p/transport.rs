@@ -0,0 +1,274 @@
+use async_trait::async_trait;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_socks::tcp::Socks5Stream;
+use uuid::Uuid;
+
+/// Any byte stream a `Connection` can run over
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+/// How outbound connections reach a peer
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn connect(&self, host: &str, port: u16) -> Result<BoxedStream>;
+}
+
+/// Which SOCKS credentials Tor sees. Tor only puts streams with the same credentials on
+/// the same circuit, so distinct credentials keep unrelated connections apart.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamIsolation {
+    /// Share circuits with everything else using the proxy
+    #[default]
+    None,
+    /// Share circuits only with streams using the same credentials
+    Credentials { username: String, password: String },
+    /// A fresh circuit for every connection
+    PerConnection,
+}
+
+/// Connects through Tor's SOCKS5 proxy, needed to reach `.onion` peers
+pub struct TorTransport {
+    proxy: String,
+    isolation: StreamIsolation,
+}
+
+impl TorTransport {
+    /// `proxy` is the `host:port` of Tor's SOCKS port
+    pub fn new(proxy: impl Into<String>) -> Self {
+        Self {
+            proxy: proxy.into(),
+            isolation: StreamIsolation::None,
+        }
+    }
+
+    pub fn with_isolation(mut self, isolation: StreamIsolation) -> Self {
+        self.isolation = isolation;
+        self
+    }
+}
+
+#[async_trait]
+impl Transport for TorTransport {
+    async fn connect(&self, host: &str, port: u16) -> Result<BoxedStream> {
+        let proxy = self.proxy.as_str();
+        let stream = match &self.isolation {
+            StreamIsolation::None => Socks5Stream::connect(proxy, (host, port)).await,
+            StreamIsolation::Credentials { username, password } => {
+                Socks5Stream::connect_with_password(proxy, (host, port), username, password).await
+            }
+            StreamIsolation::PerConnection => {
+                let username = Uuid::new_v4().to_string();
+                Socks5Stream::connect_with_password(proxy, (host, port), &username, "isolate").await
+            }
+        }
+        .with_context(|| format!("Failed to connect to {host}:{port} through Tor at {proxy}"))?;
+
+        Ok(Box::new(stream.into_inner()))
+    }
+}
+
+/// Plain TCP, for localhost and regtest networks without Tor
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn connect(&self, host: &str, port: u16) -> Result<BoxedStream> {
+        let stream = TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("Failed to connect to {host}:{port}"))?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Peers in the same process, connected through in-memory pipes. Clones share one network.
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    listeners: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<DuplexStream>>>>,
+}
+
+/// Incoming side of a `MemoryTransport` address
+pub struct MemoryListener {
+    incoming: mpsc::UnboundedReceiver<DuplexStream>,
+}
+
+impl MemoryListener {
+    /// The next connection, or `None` once the transport is gone
+    pub async fn accept(&mut self) -> Option<DuplexStream> {
+        self.incoming.recv().await
+    }
+}
+
+impl MemoryTransport {
+    const PIPE_CAPACITY: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept connections to `host:port`, replacing any earlier listener there
+    pub fn listen(&self, host: &str, port: u16) -> MemoryListener {
+        let (sender, incoming) = mpsc::unbounded_channel();
+        self.listeners.lock().unwrap().insert(format!("{host}:{port}"), sender);
+        MemoryListener { incoming }
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn connect(&self, host: &str, port: u16) -> Result<BoxedStream> {
+        let (local, remote) = tokio::io::duplex(Self::PIPE_CAPACITY);
+        let address = format!("{host}:{port}");
+        let listeners = self.listeners.lock().unwrap();
+        listeners
+            .get(&address)
+            .and_then(|listener| listener.send(remote).ok())
+            .ok_or_else(|| anyhow!("Connection to {address} refused: nothing is listening"))?;
+        Ok(Box::new(local))
+    }
+}
+
+/// The `transport` section of config.json, e.g.
+/// `{ "type": "tor", "proxy": "127.0.0.1:9050", "isolation": "per_connection" }` or `{ "type": "tcp" }`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransportConfig {
+    Tor {
+        #[serde(default = "default_tor_proxy")]
+        proxy: String,
+        #[serde(default)]
+        isolation: StreamIsolation,
+    },
+    Tcp,
+}
+
+fn default_tor_proxy() -> String {
+    "127.0.0.1:9050".into()
+}
+
+impl Default for TransportConfig {
+    /// Tor's default SOCKS port, without isolation
+    fn default() -> Self {
+        Self::Tor {
+            proxy: default_tor_proxy(),
+            isolation: StreamIsolation::None,
+        }
+    }
+}
+
+impl TransportConfig {
+    pub fn build(&self) -> Arc<dyn Transport> {
+        match self {
+            Self::Tor { proxy, isolation } => {
+                Arc::new(TorTransport::new(proxy.clone()).with_isolation(isolation.clone()))
+            }
+            Self::Tcp => Arc::new(TcpTransport),
+        }
+    }
+}
+
+/// The transport selected in config.json
+pub fn configured_transport() -> Result<Arc<dyn Transport>> {
+    let json = std::fs::read_to_string("config.json")?;
+    let config: crate::utils::config::Config = serde_json::from_str(&json)?;
+    Ok(config.transport.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn memory_transport_connects_listeners() {
+        let transport = MemoryTransport::new();
+        let mut listener = transport.listen("peer.onion", 9999);
+
+        let mut client = transport.connect("peer.onion", 9999).await.unwrap();
+        let mut server = listener.accept().await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+
+        let mut received = [0; 5];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"hello");
+
+        assert!(transport.connect("peer.onion", 8888).await.is_err());
+    }
+
+    /// A length-prefixed SOCKS5 string
+    async fn read_field(socket: &mut TcpStream) -> String {
+        let len = socket.read_u8().await.unwrap();
+        let mut field = vec![0; len as usize];
+        socket.read_exact(&mut field).await.unwrap();
+        String::from_utf8(field).unwrap()
+    }
+
+    /// Plays Tor's SOCKS port for one connection, answering username/password auth and
+    /// returning the credentials and target it was given
+    async fn fake_socks_proxy(listener: TcpListener) -> (String, String, String) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        let mut greeting = [0; 2];
+        socket.read_exact(&mut greeting).await.unwrap();
+        let mut methods = vec![0; greeting[1] as usize];
+        socket.read_exact(&mut methods).await.unwrap();
+        assert!(methods.contains(&2), "client did not offer username/password auth");
+        socket.write_all(&[5, 2]).await.unwrap();
+
+        assert_eq!(socket.read_u8().await.unwrap(), 1);
+        let username = read_field(&mut socket).await;
+        let password = read_field(&mut socket).await;
+        socket.write_all(&[1, 0]).await.unwrap();
+
+        let mut request = [0; 4];
+        socket.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, [5, 1, 0, 3], "expected a CONNECT to a domain name");
+        let host = read_field(&mut socket).await;
+        let port = socket.read_u16().await.unwrap();
+        socket.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+
+        (username, password, format!("{host}:{port}"))
+    }
+
+    #[tokio::test]
+    async fn tor_transport_sends_isolation_credentials() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let socks = tokio::spawn(fake_socks_proxy(listener));
+
+        let transport = TorTransport::new(proxy).with_isolation(StreamIsolation::Credentials {
+            username: "trade-1".into(),
+            password: "secret".into(),
+        });
+        transport.connect("peer.onion", 9999).await.unwrap();
+
+        let (username, password, target) = socks.await.unwrap();
+        assert_eq!((username.as_str(), password.as_str()), ("trade-1", "secret"));
+        assert_eq!(target, "peer.onion:9999");
+    }
+
+    #[test]
+    fn transport_is_selected_by_config() {
+        let tcp: TransportConfig = serde_json::from_str(r#"{ "type": "tcp" }"#).unwrap();
+        assert_eq!(tcp, TransportConfig::Tcp);
+
+        let tor: TransportConfig = serde_json::from_str(r#"{ "type": "tor", "isolation": "per_connection" }"#).unwrap();
+        assert_eq!(
+            tor,
+            TransportConfig::Tor {
+                proxy: "127.0.0.1:9050".into(),
+                isolation: StreamIsolation::PerConnection,
+            }
+        );
+    }
+}
@@ -1,11 +1,38 @@
 use serde::Deserialize;
+use std::path::PathBuf;
+use crate::p2p::transport::TransportConfig;
 
 
 #[derive(Deserialize)]
 pub struct Config {
-    pub(crate) secret: String,
     pub(crate) node_host: String,
     pub(crate) node_port: i32,
     pub(crate) version: String,
-    pub(crate) p2p_version: String
-}
\ No newline at end of file
+    pub(crate) p2p_version: String,
+    #[serde(default)]
+    pub(crate) transport: TransportConfig,
+    /// `host:port` of the seed node to bootstrap from
+    #[serde(default = "default_seed_node")]
+    pub(crate) seed_node: String,
+    /// Where the signature and encryption keys are kept, like the `keys` dir of a Haveno data directory
+    #[serde(default = "default_key_dir")]
+    pub(crate) key_dir: PathBuf,
+    /// Encrypts the key files when set
+    #[serde(default)]
+    pub(crate) key_password: Option<String>,
+    /// Where network data such as the sequence number map is persisted, like Haveno's `db` dir
+    #[serde(default = "default_db_dir")]
+    pub(crate) db_dir: PathBuf,
+}
+
+fn default_seed_node() -> String {
+    "5i6blbmuflq4s4im6zby26a7g22oef6kyp7vbwyru6oq5e36akzo3ayd.onion:2001".into()
+}
+
+fn default_key_dir() -> PathBuf {
+    "keys".into()
+}
+
+fn default_db_dir() -> PathBuf {
+    "db".into()
+}
@@ -1,30 +1,137 @@
-use anyhow::Error;
+use anyhow::Result;
 
-use crate::generated::io_haveno_protobuffer::{AckMessage, NodeAddress};
+use crate::generated::io_haveno_protobuffer::{AckMessage, NodeAddress, SupportType};
+use crate::utils::network::envelope::EnvMsg;
+use crate::utils::network::message::message_name;
 use uuid::Uuid;
 
-pub async fn build_ack()-> Result<AckMessage, Error> {
-    // Get node address from config
-    let json = std::fs::read_to_string("config.json")?;
-    let config: crate::utils::config::Config = serde_json::from_str(&json)?;
-    
-    let uid = Uuid::new_v4().to_string();
-
-    let ack_message = AckMessage {
-            uid: uid,
-            sender_node_address: Some(NodeAddress {
-                host_name: config.node_host.to_string(),
-                port: config.node_port.abs(),
-            }).clone(),
-            success: true,
-            source_type: String::default(),
-            source_msg_class_name: String::default(),
-            source_uid: String::default(),
-            source_id: String::default(),
-            error_message: String::default(),
-            updated_multisig_hex: String::default(),
+/// What an `AckMessage` says about the message it acknowledges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AckSource {
+    /// Haveno's `AckMessageSourceType` name, e.g. `TRADE_MESSAGE`
+    pub source_type: &'static str,
+    pub source_msg_class_name: &'static str,
+    pub source_uid: String,
+    /// Trade or offer id the message belongs to
+    pub source_id: String,
+}
+
+/// The ack source for `message`, or `None` if Haveno doesn't expect this type to be acked
+pub fn ack_source(message: &EnvMsg) -> Option<AckSource> {
+    let (source_type, uid, id) = match message {
+        EnvMsg::OfferAvailabilityRequest(m) => ("OFFER_MESSAGE", &m.uid, &m.offer_id),
+        EnvMsg::SignOfferRequest(m) => ("OFFER_MESSAGE", &m.uid, &m.offer_id),
+        // A trade's id is the id of the offer it was taken from
+        EnvMsg::InitTradeRequest(m) => ("TRADE_MESSAGE", &m.uid, &m.offer_id),
+        EnvMsg::InitMultisigRequest(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::SignContractRequest(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::SignContractResponse(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::DepositRequest(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::DepositResponse(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::DepositsConfirmedMessage(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::PaymentSentMessage(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::PaymentReceivedMessage(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::MediatedPayoutTxSignatureMessage(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::MediatedPayoutTxPublishedMessage(m) => ("TRADE_MESSAGE", &m.uid, &m.trade_id),
+        EnvMsg::DisputeOpenedMessage(m) => {
+            let trade_id = m.dispute.as_ref().map(|d| &d.trade_id);
+            (support_source_type(m.r#type), &m.uid, trade_id?)
+        }
+        EnvMsg::DisputeClosedMessage(m) => {
+            let trade_id = m.dispute_result.as_ref().map(|r| &r.trade_id);
+            (support_source_type(m.r#type), &m.uid, trade_id?)
+        }
+        EnvMsg::ChatMessage(m) => (support_source_type(m.r#type), &m.uid, &m.trade_id),
+        _ => return None,
+    };
+
+    Some(AckSource {
+        source_type,
+        source_msg_class_name: message_name(message),
+        source_uid: uid.clone(),
+        source_id: id.clone(),
+    })
+}
+
+/// Dispute and chat messages are acked under the type of support case they belong to
+fn support_source_type(support_type: i32) -> &'static str {
+    match SupportType::try_from(support_type) {
+        Ok(SupportType::Arbitration) => "ARBITRATION_MESSAGE",
+        Ok(SupportType::Mediation) => "MEDIATION_MESSAGE",
+        Ok(SupportType::Trade) => "TRADE_CHAT_MESSAGE",
+        Ok(SupportType::Refund) => "REFUND_MESSAGE",
+        Err(_) => "UNDEFINED",
+    }
+}
+
+/// Acknowledge `source`, reporting the handler's `result` back to the sender
+pub fn build_ack(source: &AckSource, sender_node_address: NodeAddress, result: &Result<()>) -> AckMessage {
+    AckMessage {
+        uid: Uuid::new_v4().to_string(),
+        sender_node_address: Some(sender_node_address),
+        source_type: source.source_type.to_string(),
+        source_msg_class_name: source.source_msg_class_name.to_string(),
+        source_uid: source.source_uid.clone(),
+        source_id: source.source_id.clone(),
+        success: result.is_ok(),
+        error_message: match result {
+            Ok(()) => String::default(),
+            Err(e) => e.to_string(),
+        },
+        updated_multisig_hex: String::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::{ChatMessage, DepositRequest, Ping};
+    use anyhow::anyhow;
+
+    fn our_address() -> NodeAddress {
+        NodeAddress {
+            host_name: "example.onion".into(),
+            port: 9999,
+        }
+    }
+
+    #[test]
+    fn acks_reference_the_trade_message() {
+        let request = DepositRequest {
+            trade_id: "trade-1".into(),
+            uid: "uid-1".into(),
+            ..Default::default()
+        };
+        let source = ack_source(&EnvMsg::DepositRequest(request)).unwrap();
+
+        let ack = build_ack(&source, our_address(), &Ok(()));
+        assert_eq!(ack.source_type, "TRADE_MESSAGE");
+        assert_eq!(ack.source_msg_class_name, "DepositRequest");
+        assert_eq!(ack.source_uid, "uid-1");
+        assert_eq!(ack.source_id, "trade-1");
+        assert!(ack.success);
+        assert!(ack.error_message.is_empty());
+
+        let nack = build_ack(&source, our_address(), &Err(anyhow!("deposit tx is invalid")));
+        assert!(!nack.success);
+        assert_eq!(nack.error_message, "deposit tx is invalid");
+    }
+
+    #[test]
+    fn chat_messages_use_the_support_type() {
+        let chat = ChatMessage {
+            trade_id: "trade-2".into(),
+            uid: "uid-2".into(),
+            r#type: SupportType::Mediation as i32,
+            ..Default::default()
         };
+        let source = ack_source(&EnvMsg::ChatMessage(chat)).unwrap();
+        assert_eq!(source.source_type, "MEDIATION_MESSAGE");
+    }
 
-    // Set the message type to ACK
-    Ok (ack_message)
-}
\ No newline at end of file
+    #[test]
+    fn network_messages_are_not_acked() {
+        let ping = EnvMsg::Ping(Ping { nonce: 1, last_round_trip_time: 0 });
+        assert_eq!(ack_source(&ping), None);
+    }
+}
@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+
+use bytes::{Buf, BufMut, BytesMut};
+use prost::Message;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::generated::io_haveno_protobuffer::{
+    AckMessage, CloseConnectionMessage, GetPeersRequest, GetPeersResponse, GetUpdatedDataRequest, NetworkEnvelope,
+    Ping, Pong, PreliminaryGetDataRequest,
+};
+use crate::utils::network::error::FrameError;
+use crate::utils::network::message::EnvelopeMessage;
+
+/// Haveno refuses messages above 10 MB (`Connection.PERMITTED_MESSAGE_SIZE`)
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 10 * 1024 * 1024;
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A u64 varint never takes more than 10 bytes
+const MAX_VARINT_LEN: usize = 10;
+
+/// `NetworkEnvelope.message_version`, which Haveno writes ahead of the message itself
+const MESSAGE_VERSION_TAG: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+
+/// How the length of each envelope is written on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// Protobuf varint prefix, what Haveno and Bisq peers use (`writeDelimitedTo`)
+    #[default]
+    Varint,
+    /// 4-byte big-endian length prefix, as written by legacy tooling
+    BigEndianU32,
+    /// Pick one from the first byte of the stream and keep it for the rest of the connection.
+    /// Meant for diagnostic tools that don't know what the other side speaks.
+    Auto,
+}
+
+impl Framing {
+    /// Guess the framing from the first byte of a stream.
+    ///
+    /// No valid envelope is empty, so a varint prefix never starts with 0, whereas a
+    /// big-endian prefix always does for frames below 16 MB.
+    pub fn detect(first_byte: u8) -> Self {
+        if first_byte == 0 { Self::BigEndianU32 } else { Self::Varint }
+    }
+
+    /// Read the length prefix from the front of `buf` without consuming it.
+    /// Returns the length and the size of the prefix, or `None` if more bytes are needed.
+    pub(crate) fn peek_length(self, buf: &[u8]) -> Result<Option<(u64, usize)>, FrameError> {
+        match self {
+            Self::Varint => peek_varint(buf),
+            Self::BigEndianU32 => Ok(buf
+                .get(..4)
+                .map(|prefix| (u64::from(u32::from_be_bytes(prefix.try_into().unwrap())), 4))),
+            Self::Auto => match buf.first() {
+                Some(&byte) => Self::detect(byte).peek_length(buf),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+/// Per-message-type size budgets, keyed by the field number of the message in
+/// `NetworkEnvelope`'s `oneof` (`EnvelopeMessage::TAG`, e.g. `Ping::TAG`).
+///
+/// Types without a budget are only bounded by the connection's frame limit.
+#[derive(Debug, Clone)]
+pub struct MessageSizeLimits {
+    limits: HashMap<u32, usize>,
+}
+
+impl MessageSizeLimits {
+    /// No per-type budgets at all
+    pub fn unlimited() -> Self {
+        Self { limits: HashMap::new() }
+    }
+
+    pub fn with_limit(mut self, tag: u32, limit: usize) -> Self {
+        self.limits.insert(tag, limit);
+        self
+    }
+
+    pub fn limit_for(&self, tag: u32) -> Option<usize> {
+        self.limits.get(&tag).copied()
+    }
+}
+
+impl Default for MessageSizeLimits {
+    /// Tight budgets for the small control messages, generous ones for the sync requests
+    /// (they carry one excluded key per known entry). Data responses are left to the frame limit.
+    fn default() -> Self {
+        Self::unlimited()
+            .with_limit(PreliminaryGetDataRequest::TAG, 5 * 1024 * 1024)
+            .with_limit(GetUpdatedDataRequest::TAG, 5 * 1024 * 1024)
+            .with_limit(GetPeersRequest::TAG, 1024 * 1024)
+            .with_limit(GetPeersResponse::TAG, 1024 * 1024)
+            .with_limit(Ping::TAG, 1024)
+            .with_limit(Pong::TAG, 1024)
+            .with_limit(CloseConnectionMessage::TAG, 4 * 1024)
+            .with_limit(AckMessage::TAG, 64 * 1024)
+    }
+}
+
+/// Limits and timeouts applied to a framed envelope stream
+#[derive(Debug, Clone)]
+pub struct CodecConfig {
+    pub framing: Framing,
+    pub max_frame_size: usize,
+    pub message_limits: MessageSizeLimits,
+    /// `None` waits forever, which is what long-lived connections want
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for CodecConfig {
+    fn default() -> Self {
+        Self {
+            framing: Framing::default(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            message_limits: MessageSizeLimits::default(),
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+        }
+    }
+}
+
+/// Length-delimited `NetworkEnvelope` codec. With the default varint framing this is
+/// Java's `writeDelimitedTo` / `parseDelimitedFrom`.
+///
+/// Limits are checked against the length prefix and the first bytes of the envelope,
+/// so an oversized frame is rejected before its body is buffered.
+#[derive(Debug, Clone)]
+pub struct NetworkEnvelopeCodec {
+    /// Replaced by the detected framing once an `Auto` codec has seen its first byte
+    framing: Framing,
+    max_frame_size: usize,
+    message_limits: MessageSizeLimits,
+}
+
+impl NetworkEnvelopeCodec {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            framing: Framing::default(),
+            max_frame_size,
+            message_limits: MessageSizeLimits::unlimited(),
+        }
+    }
+
+    pub fn from_config(config: &CodecConfig) -> Self {
+        Self {
+            framing: config.framing,
+            max_frame_size: config.max_frame_size,
+            message_limits: config.message_limits.clone(),
+        }
+    }
+
+    pub fn with_message_limits(mut self, message_limits: MessageSizeLimits) -> Self {
+        self.message_limits = message_limits;
+        self
+    }
+
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// The framing in use, `Auto` until the first byte has been decoded
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Check a frame of `len` bytes against the connection limit and, once the
+    /// message tag is buffered, against that message type's budget
+    fn check_frame(&self, len: u64, body: &[u8]) -> Result<(), FrameError> {
+        if len > self.max_frame_size as u64 {
+            return Err(FrameError::FrameTooLarge { len, limit: self.max_frame_size });
+        }
+
+        let len = len as usize;
+        let Some(tag) = peek_message_tag(&body[..body.len().min(len)])? else {
+            return Ok(());
+        };
+        match self.message_limits.limit_for(tag) {
+            Some(limit) if len > limit => Err(FrameError::MessageTooLarge { tag, len, limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for NetworkEnvelopeCodec {
+    fn default() -> Self {
+        Self::from_config(&CodecConfig::default())
+    }
+}
+
+/// Read a varint from the front of `buf` without consuming it.
+/// Returns the value and the number of bytes it occupies, or `None` if more bytes are needed.
+fn peek_varint(buf: &[u8]) -> Result<Option<(u64, usize)>, FrameError> {
+    let mut value = 0u64;
+    for (i, &byte) in buf.iter().take(MAX_VARINT_LEN).enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+
+    if buf.len() >= MAX_VARINT_LEN {
+        return Err(FrameError::VarintTooLong);
+    }
+    Ok(None)
+}
+
+/// Find the `oneof` field number of the message inside a (possibly partial) envelope body.
+/// Returns `None` if not enough of the body is buffered yet.
+fn peek_message_tag(mut body: &[u8]) -> Result<Option<u32>, FrameError> {
+    loop {
+        let Some((key, key_len)) = peek_varint(body)? else {
+            return Ok(None);
+        };
+        if key >> 3 != MESSAGE_VERSION_TAG || key & 0x7 != LENGTH_DELIMITED {
+            return Ok(Some((key >> 3) as u32));
+        }
+
+        let Some((value_len, len_len)) = peek_varint(&body[key_len..])? else {
+            return Ok(None);
+        };
+        let skip = (key_len + len_len) as u64 + value_len;
+        if skip > body.len() as u64 {
+            return Ok(None);
+        }
+        body = &body[skip as usize..];
+    }
+}
+
+impl Decoder for NetworkEnvelopeCodec {
+    type Item = NetworkEnvelope;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<NetworkEnvelope>, FrameError> {
+        if self.framing == Framing::Auto {
+            match src.first() {
+                Some(&byte) => self.framing = Framing::detect(byte),
+                None => return Ok(None),
+            }
+        }
+
+        let Some((len, header_len)) = self.framing.peek_length(src)? else {
+            return Ok(None);
+        };
+        self.check_frame(len, &src[header_len..])?;
+
+        let len = len as usize;
+        if src.len() < header_len + len {
+            src.reserve(header_len + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(header_len);
+        let body = src.split_to(len);
+        Ok(Some(NetworkEnvelope::decode(body.freeze())?))
+    }
+}
+
+impl Encoder<&NetworkEnvelope> for NetworkEnvelopeCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, env: &NetworkEnvelope, dst: &mut BytesMut) -> Result<(), FrameError> {
+        let len = env.encoded_len();
+        if len > self.max_frame_size {
+            return Err(FrameError::FrameTooLarge { len: len as u64, limit: self.max_frame_size });
+        }
+
+        dst.reserve(MAX_VARINT_LEN + len);
+        match self.framing {
+            Framing::BigEndianU32 => {
+                let prefix = u32::try_from(len)
+                    .map_err(|_| FrameError::FrameTooLarge { len: len as u64, limit: u32::MAX as usize })?;
+                dst.put_u32(prefix);
+                env.encode(dst)
+            }
+            // An `Auto` codec that hasn't heard from the peer yet speaks Haveno's framing
+            Framing::Varint | Framing::Auto => env.encode_length_delimited(dst),
+        }
+        .expect("buffer capacity was reserved above");
+        Ok(())
+    }
+}
+
+impl Encoder<NetworkEnvelope> for NetworkEnvelopeCodec {
+    type Error = FrameError;
+
+    fn encode(&mut self, env: NetworkEnvelope, dst: &mut BytesMut) -> Result<(), FrameError> {
+        self.encode(&env, dst)
+    }
+}
+
+/// Drives a `NetworkEnvelopeCodec` over any async byte stream (TCP, SOCKS5, duplex pipes).
+///
+/// Reading needs `S: AsyncRead`, writing needs `S: AsyncWrite`, so the halves of a split
+/// stream can each be wrapped on their own.
+pub struct EnvelopeStream<S> {
+    inner: S,
+    codec: NetworkEnvelopeCodec,
+    config: CodecConfig,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+}
+
+impl<S> EnvelopeStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self::with_config(inner, CodecConfig::default())
+    }
+
+    pub fn with_config(inner: S, config: CodecConfig) -> Self {
+        Self {
+            inner,
+            codec: NetworkEnvelopeCodec::from_config(&config),
+            config,
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+        }
+    }
+
+    pub fn config(&self) -> &CodecConfig {
+        &self.config
+    }
+
+    /// The framing in use, which for `Framing::Auto` is only known after the first read
+    pub fn framing(&self) -> Framing {
+        self.codec.framing()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Bytes already read from the stream but not yet decoded are dropped
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> EnvelopeStream<S> {
+    /// Read the next envelope. Returns `None` when the peer closed the stream between frames.
+    pub async fn read_envelope(&mut self) -> Result<Option<NetworkEnvelope>, FrameError> {
+        match self.config.read_timeout {
+            Some(limit) => timeout(limit, self.read_frame())
+                .await
+                .map_err(|_| FrameError::Timeout(limit))?,
+            None => self.read_frame().await,
+        }
+    }
+
+    async fn read_frame(&mut self) -> Result<Option<NetworkEnvelope>, FrameError> {
+        loop {
+            if let Some(envelope) = self.codec.decode(&mut self.read_buf)? {
+                return Ok(Some(envelope));
+            }
+
+            if self.inner.read_buf(&mut self.read_buf).await? == 0 {
+                if self.read_buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(FrameError::Truncated { buffered: self.read_buf.len() });
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> EnvelopeStream<S> {
+    pub async fn write_envelope(&mut self, env: &NetworkEnvelope) -> Result<(), FrameError> {
+        self.write_buf.clear();
+        self.codec.encode(env, &mut self.write_buf)?;
+
+        let write = async {
+            self.inner.write_all(&self.write_buf).await?;
+            self.inner.flush().await
+        };
+
+        match self.config.write_timeout {
+            Some(limit) => timeout(limit, write)
+                .await
+                .map_err(|_| FrameError::Timeout(limit))??,
+            None => write.await?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::network_envelope::Message as EnvMsg;
+
+    fn ping(nonce: i32) -> NetworkEnvelope {
+        NetworkEnvelope {
+            message_version: "0X".into(),
+            message: Some(EnvMsg::Ping(Ping { nonce, last_round_trip_time: 0 })),
+        }
+    }
+
+    #[test]
+    fn decodes_frames_split_across_reads() {
+        let mut codec = NetworkEnvelopeCodec::default();
+        let mut encoded = BytesMut::new();
+        codec.encode(&ping(1), &mut encoded).unwrap();
+        codec.encode(&ping(2), &mut encoded).unwrap();
+
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&encoded[..3]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(&encoded[3..]);
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping(1)));
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping(2)));
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_frames_above_limit() {
+        let mut buf = BytesMut::new();
+        prost::encoding::encode_varint(1024, &mut buf);
+
+        let mut codec = NetworkEnvelopeCodec::new(512);
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert!(matches!(err, FrameError::FrameTooLarge { len: 1024, limit: 512 }));
+        assert!(err.is_protocol_violation());
+    }
+
+    #[test]
+    fn rejects_message_over_budget_before_body_is_buffered() {
+        let mut full = BytesMut::new();
+        NetworkEnvelopeCodec::default().encode(&ping(1), &mut full).unwrap();
+
+        // Only the length prefix, message_version and the Ping tag have arrived
+        let header_len = 1 + 2 + "0X".len() + 1;
+        let mut buf = BytesMut::from(&full[..header_len]);
+
+        let limits = MessageSizeLimits::unlimited().with_limit(Ping::TAG, 2);
+        let mut codec = NetworkEnvelopeCodec::default().with_message_limits(limits);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(FrameError::MessageTooLarge { tag: 7, limit: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_overlong_length_prefix() {
+        let mut buf = BytesMut::from(&[0xff; 11][..]);
+        let mut codec = NetworkEnvelopeCodec::default();
+        assert!(matches!(codec.decode(&mut buf), Err(FrameError::VarintTooLong)));
+    }
+
+    #[test]
+    fn big_endian_framing_round_trips() {
+        let mut codec = NetworkEnvelopeCodec::default().with_framing(Framing::BigEndianU32);
+        let mut buf = BytesMut::new();
+        codec.encode(&ping(3), &mut buf).unwrap();
+        assert_eq!(&buf[..4], &(ping(3).encoded_len() as u32).to_be_bytes());
+
+        assert_eq!(codec.decode(&mut buf).unwrap(), Some(ping(3)));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn auto_framing_detects_either_prefix() {
+        for framing in [Framing::Varint, Framing::BigEndianU32] {
+            let mut buf = BytesMut::new();
+            let mut writer = NetworkEnvelopeCodec::default().with_framing(framing);
+            writer.encode(&ping(4), &mut buf).unwrap();
+            writer.encode(&ping(5), &mut buf).unwrap();
+
+            let mut reader = NetworkEnvelopeCodec::default().with_framing(Framing::Auto);
+            assert_eq!(reader.decode(&mut buf).unwrap(), Some(ping(4)));
+            assert_eq!(reader.framing(), framing);
+            assert_eq!(reader.decode(&mut buf).unwrap(), Some(ping(5)));
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_over_duplex_pipe() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut client = EnvelopeStream::new(client);
+        let mut server = EnvelopeStream::new(server);
+
+        let writer = tokio::spawn(async move {
+            client.write_envelope(&ping(7)).await.unwrap();
+            client.write_envelope(&ping(8)).await.unwrap();
+        });
+
+        assert_eq!(server.read_envelope().await.unwrap(), Some(ping(7)));
+        assert_eq!(server.read_envelope().await.unwrap(), Some(ping(8)));
+        writer.await.unwrap();
+        assert_eq!(server.read_envelope().await.unwrap(), None);
+    }
+}
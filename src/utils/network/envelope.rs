@@ -1,61 +1,80 @@
 use crate::generated::io_haveno_protobuffer::{network_envelope, NetworkEnvelope};
+use crate::utils::network::codec::{CodecConfig, EnvelopeStream, Framing, NetworkEnvelopeCodec};
+use crate::utils::network::error::FrameError;
 
 use bytes::BytesMut;
-use prost::Message;
-use anyhow::Result;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-    time::{timeout, Duration},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    time::timeout,
 };
+use tokio_util::codec::Decoder;
 
 pub type EnvMsg = network_envelope::Message;
 
-pub async fn send_envelope(stream: &mut TcpStream, env: &NetworkEnvelope) -> Result<()> {
-    let mut body = Vec::new();
-    env.encode(&mut body)?;
-
-    // Encode the varint length prefix using BytesMut
-    let mut frame = BytesMut::with_capacity(10 + body.len());
-    prost::encoding::encode_varint(body.len() as u64, &mut frame);
-
-    // Append the message body
-    frame.extend_from_slice(&body);
+/// Write a single envelope with the default (varint) framing to any async stream
+pub async fn send_envelope<S: AsyncWrite + Unpin>(stream: &mut S, env: &NetworkEnvelope) -> Result<(), FrameError> {
+    EnvelopeStream::new(stream).write_envelope(env).await
+}
 
-    stream.write_all(&frame).await?;
-    Ok(())
+/// Read a single envelope with the default (varint) framing from any async stream.
+///
+/// The stream is read unbuffered so nothing past the frame is consumed. Use `EnvelopeStream`
+/// to read more than one envelope from the same connection.
+pub async fn recv_envelope<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<NetworkEnvelope>, FrameError> {
+    recv_envelope_with_config(stream, &CodecConfig::default()).await
 }
 
-pub async fn recv_envelope(stream: &mut TcpStream) -> Result<Option<NetworkEnvelope>> {
-    let mut varint_buf = BytesMut::with_capacity(10);
-    let timeout_duration = Duration::from_secs(30);
-
-    // Read varint length byte-by-byte
-    loop {
-        let mut byte = [0u8; 1];
-        timeout(timeout_duration, stream.read_exact(&mut byte)).await??;
-        varint_buf.extend_from_slice(&byte);
-        if byte[0] & 0x80 == 0 {
-            break;
-        }
-    }
+pub async fn recv_envelope_with_config<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    config: &CodecConfig,
+) -> Result<Option<NetworkEnvelope>, FrameError> {
+    let read = async {
+        let mut frame = BytesMut::with_capacity(16);
+        let mut framing = config.framing;
+        let len = loop {
+            let mut byte = [0u8; 1];
+            if stream.read(&mut byte).await? == 0 {
+                if frame.is_empty() {
+                    return Ok(None);
+                }
+                return Err(FrameError::Truncated { buffered: frame.len() });
+            }
+            frame.extend_from_slice(&byte);
+            if framing == Framing::Auto {
+                framing = Framing::detect(byte[0]);
+            }
+            if let Some((len, _)) = framing.peek_length(&frame)? {
+                break len;
+            }
+        };
 
-    let mut cursor = &varint_buf[..];
-    let len = prost::encoding::decode_varint(&mut cursor)? as usize;
+        // Only the limit check runs on the prefix alone, the per-type budget is checked
+        // by the decoder once the body is in
+        if len > config.max_frame_size as u64 {
+            return Err(FrameError::FrameTooLarge { len, limit: config.max_frame_size });
+        }
 
-    // Read message body
-    let mut msg_buf = vec![0u8; len];
-    timeout(timeout_duration, stream.read_exact(&mut msg_buf)).await??;
+        let header_len = frame.len();
+        frame.resize(header_len + len as usize, 0);
+        stream.read_exact(&mut frame[header_len..]).await?;
+        NetworkEnvelopeCodec::from_config(config)
+            .with_framing(framing)
+            .decode(&mut frame)
+    };
 
-    let envelope = NetworkEnvelope::decode(&*msg_buf)?;
-    Ok(Some(envelope))
+    match config.read_timeout {
+        Some(limit) => timeout(limit, read)
+            .await
+            .map_err(|_| FrameError::Timeout(limit))?,
+        None => read.await,
+    }
 }
 
 pub fn build_envelope(message: network_envelope::Message) -> NetworkEnvelope {
     let json = std::fs::read_to_string("config.json");
     let config: crate::utils::config::Config = serde_json::from_str(&json.unwrap()).unwrap();
     NetworkEnvelope {
-        message_version: config.p2p_version.into(), // customize if needed
+        message_version: config.p2p_version, // customize if needed
         message: Some(message),
-    }.into()
-}
\ No newline at end of file
+    }
+}
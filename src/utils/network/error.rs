@@ -0,0 +1,74 @@
+use std::{fmt, io};
+
+use tokio::time::Duration;
+
+/// Why reading or writing a framed envelope failed
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    Timeout(Duration),
+    /// The stream closed part way through a frame
+    Truncated { buffered: usize },
+    /// A length prefix (or a length inside the envelope header) ran past 10 bytes
+    VarintTooLong,
+    /// The length prefix is above the connection's frame limit
+    FrameTooLarge { len: u64, limit: usize },
+    /// The frame is within the connection limit but above the budget for its message type
+    MessageTooLarge { tag: u32, len: usize, limit: usize },
+    Decode(prost::DecodeError),
+}
+
+impl FrameError {
+    /// True when the peer sent something no honest Haveno node would send.
+    ///
+    /// The connection layer should close the connection and penalise the peer for these,
+    /// whereas I/O errors, timeouts and truncated frames can just as well be a flaky Tor circuit.
+    pub fn is_protocol_violation(&self) -> bool {
+        matches!(
+            self,
+            Self::VarintTooLong | Self::FrameTooLarge { .. } | Self::MessageTooLarge { .. } | Self::Decode(_)
+        )
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "i/o error: {e}"),
+            Self::Timeout(limit) => write!(f, "timed out after {limit:?}"),
+            Self::Truncated { buffered } => {
+                write!(f, "stream closed in the middle of a frame ({buffered} bytes buffered)")
+            }
+            Self::VarintTooLong => write!(f, "length prefix varint is too long"),
+            Self::FrameTooLarge { len, limit } => {
+                write!(f, "frame of {len} bytes exceeds limit of {limit} bytes")
+            }
+            Self::MessageTooLarge { tag, len, limit } => {
+                write!(f, "message with tag {tag} of {len} bytes exceeds its budget of {limit} bytes")
+            }
+            Self::Decode(e) => write!(f, "invalid envelope: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<prost::DecodeError> for FrameError {
+    fn from(e: prost::DecodeError) -> Self {
+        Self::Decode(e)
+    }
+}
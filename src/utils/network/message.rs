@@ -0,0 +1,118 @@
+use crate::generated::io_haveno_protobuffer::*;
+use crate::utils::network::envelope::EnvMsg;
+
+/// A message type that can travel in `NetworkEnvelope.message`
+pub trait EnvelopeMessage: Sized + Send + 'static {
+    /// Field number of the type in `NetworkEnvelope`'s `oneof`
+    const TAG: u32;
+    /// The Java class name, which is also how Haveno refers to the type in acks and logs
+    const NAME: &'static str;
+
+    fn from_envelope_message(message: EnvMsg) -> Option<Self>;
+    fn into_envelope_message(self) -> EnvMsg;
+}
+
+macro_rules! envelope_messages {
+    ($($variant:ident = $tag:literal),* $(,)?) => {
+        /// Field number of the message in `NetworkEnvelope`'s `oneof`
+        pub fn message_tag(message: &EnvMsg) -> u32 {
+            match message {
+                $(EnvMsg::$variant(_) => $tag,)*
+            }
+        }
+
+        pub fn message_name(message: &EnvMsg) -> &'static str {
+            match message {
+                $(EnvMsg::$variant(_) => stringify!($variant),)*
+            }
+        }
+
+        $(
+            impl EnvelopeMessage for $variant {
+                const TAG: u32 = $tag;
+                const NAME: &'static str = stringify!($variant);
+
+                fn from_envelope_message(message: EnvMsg) -> Option<Self> {
+                    match message {
+                        EnvMsg::$variant(message) => Some(message),
+                        _ => None,
+                    }
+                }
+
+                fn into_envelope_message(self) -> EnvMsg {
+                    EnvMsg::$variant(self)
+                }
+            }
+        )*
+    };
+}
+
+envelope_messages! {
+    PreliminaryGetDataRequest = 2,
+    GetDataResponse = 3,
+    GetUpdatedDataRequest = 4,
+    GetPeersRequest = 5,
+    GetPeersResponse = 6,
+    Ping = 7,
+    Pong = 8,
+    OfferAvailabilityRequest = 9,
+    OfferAvailabilityResponse = 10,
+    RefreshOfferMessage = 11,
+    AddDataMessage = 12,
+    RemoveDataMessage = 13,
+    RemoveMailboxDataMessage = 14,
+    CloseConnectionMessage = 15,
+    PrefixedSealedAndSignedMessage = 16,
+    PrivateNotificationMessage = 17,
+    AddPersistableNetworkPayloadMessage = 18,
+    AckMessage = 19,
+    BundleOfEnvelopes = 20,
+    GetInventoryRequest = 21,
+    GetInventoryResponse = 22,
+    SignOfferRequest = 23,
+    SignOfferResponse = 24,
+    InitTradeRequest = 25,
+    InitMultisigRequest = 26,
+    SignContractRequest = 27,
+    SignContractResponse = 28,
+    DepositRequest = 29,
+    DepositResponse = 30,
+    DepositsConfirmedMessage = 31,
+    PaymentSentMessage = 32,
+    PaymentReceivedMessage = 33,
+    DisputeOpenedMessage = 34,
+    DisputeClosedMessage = 35,
+    ChatMessage = 36,
+    MediatedPayoutTxSignatureMessage = 37,
+    MediatedPayoutTxPublishedMessage = 38,
+    FileTransferPart = 39,
+    OnionAuthRequest = 1000,
+    OnionAuthChallenge = 1001,
+    OnionAuthResponse = 1002,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn tags_match_the_wire_format() {
+        let envelope = NetworkEnvelope {
+            message_version: String::new(),
+            message: Some(Pong { request_nonce: 1 }.into_envelope_message()),
+        };
+        // No message_version, so the first byte is the key of the message field
+        let key = envelope.encode_to_vec()[0];
+        assert_eq!(u32::from(key >> 3), Pong::TAG);
+        assert_eq!(message_tag(envelope.message.as_ref().unwrap()), Pong::TAG);
+        assert_eq!(message_name(envelope.message.as_ref().unwrap()), "Pong");
+    }
+
+    #[test]
+    fn does_not_confuse_similar_names() {
+        let message = AddPersistableNetworkPayloadMessage { payload: None }.into_envelope_message();
+        assert!(AddDataMessage::from_envelope_message(message.clone()).is_none());
+        assert!(AddPersistableNetworkPayloadMessage::from_envelope_message(message).is_some());
+    }
+}
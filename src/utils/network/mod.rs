@@ -1,4 +1,7 @@
 pub mod envelope;
+pub mod codec;
+pub mod error;
+pub mod message;
 pub mod ack;
 pub mod updated_data;
 pub mod storage_payload;
\ No newline at end of file
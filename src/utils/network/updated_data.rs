@@ -1,6 +1,28 @@
-use crate::generated::io_haveno_protobuffer::{NodeAddress, GetUpdatedDataRequest};
+use anyhow::{bail, Result};
+use crate::generated::io_haveno_protobuffer::{GetDataResponse, GetUpdatedDataRequest, NodeAddress, PreliminaryGetDataRequest};
+use crate::p2p::append_only_store::AppendOnlyDataStore;
+use crate::p2p::capability;
+use crate::p2p::data_storage::P2PDataStorage;
 
-pub async fn build_get_updated_data() -> Result<GetUpdatedDataRequest, anyhow::Error> {
+/// The keys of everything we already have, so a data request only returns what's new: the
+/// truncated 20-byte hashes of the append-only payloads and the 32-byte payload hashes of the
+/// protected entries, as Haveno keys its own stores and matches `excluded_keys` against them
+pub async fn excluded_keys(storage: &P2PDataStorage, store: &AppendOnlyDataStore) -> Vec<Vec<u8>> {
+    let mut keys = store.hashes().await;
+    keys.extend(storage.hashes().await.into_iter().map(|hash| hash.to_vec()));
+    keys
+}
+
+pub fn build_preliminary_get_data(excluded_keys: Vec<Vec<u8>>, version: &str) -> PreliminaryGetDataRequest {
+    PreliminaryGetDataRequest {
+        nonce: rand::random(),
+        excluded_keys,
+        supported_capabilities: capability::SUPPORTED.to_vec(),
+        version: version.to_string(),
+    }
+}
+
+pub async fn build_get_updated_data(excluded_keys: Vec<Vec<u8>>) -> Result<GetUpdatedDataRequest, anyhow::Error> {
 
     // Get node address from config
     let json = std::fs::read_to_string("config.json")?;
@@ -12,9 +34,61 @@ pub async fn build_get_updated_data() -> Result<GetUpdatedDataRequest, anyhow::E
                 port: config.node_port.abs(),
                 host_name: config.node_host.to_string(),
             }),
-            nonce: 3424,
-            excluded_keys: vec![],
+            nonce: rand::random(),
+            excluded_keys,
             version: config.version.to_string(),
         }
     )
-}
\ No newline at end of file
+}
+
+/// Check that `response` answers our request with `nonce`, and is the kind of response we asked for
+pub fn check_get_data_response(response: &GetDataResponse, nonce: i32, is_get_updated_data_response: bool) -> Result<()> {
+    if response.request_nonce != nonce {
+        bail!("GetDataResponse for nonce {}, we sent {nonce}", response.request_nonce);
+    }
+    if response.is_get_updated_data_response != is_get_updated_data_response {
+        bail!("GetDataResponse answers the wrong kind of request");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::io_haveno_protobuffer::{
+        persistable_network_payload::Message as NetworkPayloadMessage, AccountAgeWitness, PersistableNetworkPayload,
+    };
+
+    #[tokio::test]
+    async fn excludes_what_the_stores_hold() {
+        let storage = P2PDataStorage::new();
+        let store = AppendOnlyDataStore::new();
+        assert!(excluded_keys(&storage, &store).await.is_empty());
+
+        let witness = AccountAgeWitness { hash: vec![7; 20], date: 1 };
+        store
+            .add(PersistableNetworkPayload { message: Some(NetworkPayloadMessage::AccountAgeWitness(witness)) })
+            .await
+            .unwrap();
+        assert_eq!(excluded_keys(&storage, &store).await, vec![vec![7; 20]]);
+    }
+
+    #[test]
+    fn requests_use_fresh_nonces() {
+        let nonces: std::collections::HashSet<i32> =
+            (0..8).map(|_| build_preliminary_get_data(vec![], "1.1.2").nonce).collect();
+        assert!(nonces.len() > 1);
+    }
+
+    #[test]
+    fn responses_must_echo_our_nonce() {
+        let response = GetDataResponse {
+            request_nonce: 5,
+            is_get_updated_data_response: true,
+            ..Default::default()
+        };
+        assert!(check_get_data_response(&response, 5, true).is_ok());
+        assert!(check_get_data_response(&response, 6, true).is_err());
+        assert!(check_get_data_response(&response, 5, false).is_err());
+    }
+}
@@ -2,8 +2,9 @@ use anyhow::{Result, Context};
 use prost::Message;
 use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::any::type_name;
+use crate::generated::io_haveno_protobuffer::PersistableEnvelope;
 
 pub enum Format {
     Json,
@@ -70,4 +71,20 @@ pub fn load_message<M: Message + Default + serde::de::DeserializeOwned>(
     };
 
     Ok(message)
-}
\ No newline at end of file
+}
+
+/// Read a file as Haveno's `PersistenceManager` writes them, a length-delimited `PersistableEnvelope`
+pub fn read_persistable_envelope(path: &Path) -> Result<PersistableEnvelope> {
+    let buf = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    PersistableEnvelope::decode_length_delimited(buf.as_slice())
+        .with_context(|| format!("{} is not a PersistableEnvelope", path.display()))
+}
+
+/// Write `envelope` to `path` so Haveno's `PersistenceManager` could read it
+pub fn write_persistable_envelope(path: &Path, envelope: &PersistableEnvelope) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, envelope.encode_length_delimited_to_vec())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}